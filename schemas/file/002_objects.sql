@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN object VARCHAR(150) NOT NULL DEFAULT "";
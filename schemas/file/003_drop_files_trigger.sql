@@ -0,0 +1,2 @@
+-- It set a column files never had, so every update of a version failed
+DROP TRIGGER IF EXISTS update_files_timestamp;
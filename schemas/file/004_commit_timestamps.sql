@@ -0,0 +1 @@
+ALTER TABLE commits ADD COLUMN timestamp INTEGER NOT NULL DEFAULT 0;
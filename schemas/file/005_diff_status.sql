@@ -0,0 +1 @@
+ALTER TABLE diffs ADD COLUMN status VARCHAR(150) NOT NULL DEFAULT "Success";
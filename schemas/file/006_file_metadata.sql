@@ -0,0 +1,2 @@
+ALTER TABLE files ADD COLUMN size INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE files ADD COLUMN modified INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS chunks (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    object VARCHAR(150) NOT NULL,
+    position INTEGER NOT NULL,
+    chunk VARCHAR(150) NOT NULL,
+    size INTEGER NOT NULL,
+    UNIQUE (id),
+    UNIQUE (object, position)
+);
@@ -0,0 +1 @@
+ALTER TABLE commits ADD COLUMN root_commit INTEGER NOT NULL DEFAULT 0;
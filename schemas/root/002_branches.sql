@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS branches (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    created_at INTEGER DEFAULT CURRENT_TIMESTAMP,
+    updated_at INTEGER DEFAULT CURRENT_TIMESTAMP,
+    name VARCHAR(150) NOT NULL,
+    description TEXT DEFAULT "",
+    status VARCHAR(150) DEFAULT "Active",
+    author VARCHAR(150) NOT NULL,
+    UNIQUE (id),
+    UNIQUE (name)
+);
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS tags (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    created_at INTEGER DEFAULT CURRENT_TIMESTAMP,
+    name VARCHAR(150) NOT NULL,
+    message TEXT DEFAULT "",
+    branch VARCHAR(150) NOT NULL,
+    author VARCHAR(150) NOT NULL,
+    UNIQUE (id),
+    UNIQUE (name)
+);
+
+CREATE TABLE IF NOT EXISTS tag_versions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    tag VARCHAR(150) NOT NULL,
+    path VARCHAR(150) NOT NULL,
+    object VARCHAR(150) NOT NULL,
+    UNIQUE (id),
+    UNIQUE (tag, path)
+);
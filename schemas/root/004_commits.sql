@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS commits (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    created_at INTEGER DEFAULT CURRENT_TIMESTAMP,
+    timestamp INTEGER NOT NULL,
+    message TEXT DEFAULT "",
+    branch VARCHAR(150) NOT NULL,
+    author VARCHAR(150) NOT NULL,
+    git_commit VARCHAR(150) DEFAULT "",
+    status VARCHAR(150) NOT NULL DEFAULT "Pending",
+    UNIQUE (id)
+);
+
+CREATE TABLE IF NOT EXISTS commit_files (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    root_commit INTEGER NOT NULL,
+    path VARCHAR(150) NOT NULL,
+    object VARCHAR(150) NOT NULL,
+    UNIQUE (id),
+    UNIQUE (root_commit, path)
+);
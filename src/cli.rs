@@ -9,7 +9,7 @@ use crate::todo::utils::cli::ToDoCli;
 #[cfg(feature = "vcs")]
 use crate::vcs::VcsArgs;
 
-use crate::config::ConfigArgs;
+use crate::config::{Config, ConfigArgs};
 
 use clap::Parser;
 use menva::read_env_file;
@@ -27,6 +27,10 @@ impl Cli {
         read_env_file(".env");
         let cli = Cli::parse();
         match cli.command {
+            Commands::Init => {
+                Config::init().await;
+                0
+            },
             Commands::Config(args) => args.command.handle_commands().await,
             #[cfg(feature = "vcs")]
             Commands::Vcs(args) => args.command.handle_commands().await,
@@ -63,6 +67,9 @@ enum Commands {
     /// Manage documentation from various projects
     #[command(arg_required_else_help = true)]
     Docs(DocsArgs),
+    /// Create a repository in the current directory: the .yap directory with its config,
+    /// history, logbooks and root logbook
+    Init,
     /// Tune your configurations. Set up your remote logbook, your email and so on
     #[command(arg_required_else_help = true)]
     Config(Box<ConfigArgs>),
@@ -1,18 +1,20 @@
 use core::panic;
 use std::{
-    fmt,
-    path::{Path, PathBuf},
+    env, fmt,
+    path::{Component, Path, PathBuf},
+    sync::OnceLock,
 };
 
 use crate::{
     enums::ColorWhen,
+    schemas::{migrate, ROOT_MIGRATIONS},
     utils::{struct_to_toml, toml_to_struct},
 };
 use clap::{Args, Subcommand, ValueEnum};
 use libsql::Builder;
 use menva::get_env;
 use opendal::{
-    services::{Gcs, Koofr, Pcloud},
+    services::{Fs, Gcs, Koofr, Memory, Pcloud, S3},
     Operator,
 };
 use serde::{Deserialize, Serialize};
@@ -50,9 +52,6 @@ pub enum ConfigCommands {
     Get(Config),
     /// Remove configurations
     Remove(Config),
-    /// Creates the values in the configurations file if they dont exists. In case that some
-    /// informations may be empty they will be filled if possible
-    Init,
 }
 
 impl ConfigCommands {
@@ -62,7 +61,6 @@ impl ConfigCommands {
             ConfigCommands::Set(args) => args.set().await,
             ConfigCommands::Get(args) => args.get().await,
             ConfigCommands::Remove(args) => args.remove().await,
-            ConfigCommands::Init => Config::init().await,
         };
         0
     }
@@ -78,7 +76,7 @@ pub struct Author {
 
 impl Author {
     pub fn pk(&self) -> String {
-        format!("{} <{}<>", &self.name, &self.email)
+        format!("{} <{}>", &self.name, &self.email)
     }
 }
 
@@ -206,12 +204,36 @@ pub struct Config {
     #[clap(skip)]
     #[serde(default)]
     author: Author,
+    // Data branch in use. When empty the current git branch is used
+    #[clap(skip)]
+    #[serde(default)]
+    branch: Option<String>,
+    // Files bigger than this many bytes are stored as chunks so a new version only stores
+    // the parts that changed
+    #[arg(long, required = false)]
+    #[serde(default)]
+    chunking_threshold: Option<u64>,
+    // Gitignore-style patterns ignored in every directory, on top of the .yapignore files
+    #[clap(skip)]
+    #[serde(default)]
+    ignore: Vec<String>,
+    // Directory holding the .yap directory and the one yap was run from
+    #[clap(skip)]
+    #[serde(skip)]
+    root_dir: PathBuf,
+    #[clap(skip)]
+    #[serde(skip)]
+    work_dir: PathBuf,
 }
 
 //TODO: make this file smaller so more settings are saved in the databse
 impl Config {
-    async fn init() {
-        let mut file_config = Config::new();
+    /// Bootstrap a repository in the current directory, or fill what is missing in the one
+    /// already there
+    pub async fn init() {
+        let work_dir = env::current_dir().expect("unable to read the current directory");
+        let exists = work_dir.join(".yap").is_dir();
+        let mut file_config = Config::load(&work_dir, &work_dir);
 
         file_config.set_default_history_dir().await;
         file_config.set_default_logbooks_dir().await;
@@ -230,22 +252,38 @@ impl Config {
             let client = client.databases();
             logbook_cfg.set_default_remote_db(&client).await;
         }
-        struct_to_toml(&file_config, ".yap/.config");
+        struct_to_toml(&file_config, &file_config.root(".config"));
+        match exists {
+            true => println!("Reinitialized yap repository in {:?}", work_dir),
+            false => println!("Initialized yap repository in {:?}", work_dir),
+        }
     }
 
-    //fn check_for_root() {
-    //    // TODO: we are testing to track single files.once finished change it
-    //    // we use the general .yap to track files whereever we are
-    //    match Path::new(".yap").exists() {
-    //        true => (),
-    //        false => panic!("oupsi daisy no you are not in the root buddy"),
-    //    }
-    //}
-
     pub fn author(&self) -> Author {
         self.author.clone()
     }
 
+    pub fn branch(&self) -> Option<String> {
+        self.branch.clone()
+    }
+
+    pub fn set_branch(&mut self, branch: &str) -> &mut Self {
+        self.branch = Some(branch.to_owned());
+        self
+    }
+
+    pub fn save(&self) {
+        struct_to_toml(self, &self.root(".config"));
+    }
+
+    pub fn chunking_threshold(&self) -> Option<u64> {
+        self.chunking_threshold
+    }
+
+    pub fn ignore_patterns(&self) -> &[String] {
+        &self.ignore
+    }
+
     pub fn remote_storage(&self) -> RemoteConfig {
         //TODO: do wee need to clone?
         self.remote.clone()
@@ -253,7 +291,51 @@ impl Config {
 
     pub fn root(&self, path: &str) -> String {
         //TOOD: use a path
-        format!(".yap/{path}")
+        self.root_dir
+            .join(".yap")
+            .join(path)
+            .to_str()
+            .expect("unable to convert to str")
+            .to_owned()
+    }
+
+    pub fn root_dir(&self) -> &Path {
+        &self.root_dir
+    }
+
+    /// Path relative to the repository root of a path given relative to the directory yap
+    /// was run from, or absolute
+    pub fn repository_path(&self, path: &Path) -> PathBuf {
+        let mut absolute = PathBuf::new();
+        for component in self.work_dir.join(path).components() {
+            match component {
+                Component::CurDir => {},
+                Component::ParentDir => {
+                    absolute.pop();
+                },
+                c => absolute.push(c),
+            }
+        }
+        let path = absolute.strip_prefix(&self.root_dir).unwrap_or_else(|_| {
+            panic!(
+                "{:?} is outside of the repository {:?}",
+                path, self.root_dir
+            )
+        });
+        match path.as_os_str().is_empty() {
+            true => PathBuf::from("."),
+            false => path.to_path_buf(),
+        }
+    }
+
+    /// Path given relative to the directory yap was run from, still valid once the commands
+    /// moved to the repository root. Unlike the tracked paths it can be outside of it.
+    pub fn work_path(&self, path: &Path) -> PathBuf {
+        self.work_dir.join(path)
+    }
+
+    pub fn repository_paths(&self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        paths.iter().map(|p| self.repository_path(p)).collect()
     }
 
     pub fn local_db(&self) -> String {
@@ -266,10 +348,30 @@ impl Config {
         self.root(&self.history_dir)
     }
 
-    pub fn new() -> Config {
-        // TODO: do something better to check and be in root. Maybe something like git
-        //Config::check_for_root();
-        toml_to_struct(".yap/.config")
+    /// Read the config of the repository, found like git does by walking up from the
+    /// current directory to the nearest one holding a .yap directory
+    pub fn new() -> Result<Config, String> {
+        let work_dir = env::current_dir()
+            .map_err(|err| format!("unable to read the current directory: {err}"))?;
+        let root_dir = work_dir
+            .ancestors()
+            .find(|dir| dir.join(".yap").is_dir())
+            .ok_or(
+                "not a yap repository (or any of the parent directories), run yap init",
+            )?;
+        Ok(Config::load(root_dir, &work_dir))
+    }
+
+    fn load(root_dir: &Path, work_dir: &Path) -> Config {
+        let mut config: Config = toml_to_struct(
+            root_dir
+                .join(".yap/.config")
+                .to_str()
+                .expect("unable to convert to str"),
+        );
+        config.root_dir = root_dir.to_path_buf();
+        config.work_dir = work_dir.to_path_buf();
+        config
     }
 
     async fn set_default_local_db(&mut self) -> &mut Self {
@@ -282,15 +384,7 @@ impl Config {
             .await
             .expect("unable to open to local");
         let conn = db.connect().expect("unable to connect to local db");
-        let query = match tokio::fs::read_to_string("schemas/master_logbook.sql").await {
-            Ok(sql) => sql,
-            Err(err) => {
-                panic!("{err}");
-            },
-        };
-        conn.execute_batch(&query)
-            .await
-            .expect("unable to create tables into file logbook");
+        migrate(&conn, &ROOT_MIGRATIONS).await;
         self
     }
 
@@ -340,6 +434,13 @@ pub struct RemoteConfig {
     #[serde(default)]
     bucket: Option<String>,
     #[serde(default)]
+    region: Option<String>,
+    // Needed for S3 compatible stores like MinIO, e.g. http://localhost:9000
+    #[serde(default)]
+    endpoint: Option<String>,
+    #[serde(default)]
+    access_key: Option<String>,
+    #[serde(default)]
     username: Option<String>,
     #[serde(default)]
     credentials: String,
@@ -357,7 +458,58 @@ impl RemoteConfig {
             Storage::Gcs => self.create_gcs(),
             Storage::Koofr => self.create_koofr(),
             Storage::Pcloud => self.create_pcloud(),
+            Storage::Fs => self.create_fs(),
+            Storage::Memory => Self::create_memory(),
+            Storage::S3 => self.create_s3(),
+        }
+    }
+
+    fn create_s3(&self) -> Operator {
+        let mut builder = S3::default();
+        builder.root(&self.root);
+        builder.bucket(
+            self.bucket
+                .as_ref()
+                .expect("You need to set the bucket field"),
+        );
+        builder.region(self.region.as_deref().unwrap_or("us-east-1"));
+        if let Some(endpoint) = &self.endpoint {
+            builder.endpoint(endpoint);
+        }
+        builder.access_key_id(
+            self.access_key
+                .as_ref()
+                .expect("You need to set the access_key field"),
+        );
+        builder.secret_access_key(&self.password());
+        match Operator::new(builder) {
+            Ok(op) => op.finish(),
+            Err(err) => panic!("{:?}", err),
+        }
+    }
+
+    fn create_fs(&self) -> Operator {
+        if self.root.is_empty() {
+            panic!("You need to set the root field with the directory to use as remote");
         }
+        let mut builder = Fs::default();
+        builder.root(&self.root);
+        match Operator::new(builder) {
+            Ok(op) => op.finish(),
+            Err(err) => panic!("{:?}", err),
+        }
+    }
+
+    fn create_memory() -> Operator {
+        // The data lives inside the operator so we keep a single one for the whole run,
+        // otherwise what is pushed would be lost before pulling it back
+        static MEMORY: OnceLock<Operator> = OnceLock::new();
+        MEMORY
+            .get_or_init(|| match Operator::new(Memory::default()) {
+                Ok(op) => op.finish(),
+                Err(err) => panic!("{:?}", err),
+            })
+            .clone()
     }
 
     fn create_koofr(&self) -> Operator {
@@ -431,6 +583,12 @@ pub enum Storage {
     Koofr,
     #[default]
     Pcloud,
+    /// A local directory, or a NAS mounted one, used as remote
+    Fs,
+    /// Kept in memory for the duration of the run. Useful for testing
+    Memory,
+    /// AWS S3 or any S3 compatible store like MinIO through the endpoint field
+    S3,
 }
 
 impl std::fmt::Display for Storage {
@@ -441,3 +599,38 @@ impl std::fmt::Display for Storage {
             .fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            root_dir: PathBuf::from("/repo"),
+            work_dir: PathBuf::from("/repo/data"),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_repository_path() {
+        let config = config();
+        let path = |p: &str| config.repository_path(Path::new(p));
+        assert_eq!(path("a.csv"), PathBuf::from("data/a.csv"));
+        assert_eq!(path("./raw/../a.csv"), PathBuf::from("data/a.csv"));
+        assert_eq!(path("../b.csv"), PathBuf::from("b.csv"));
+        assert_eq!(path("."), PathBuf::from("data"));
+        assert_eq!(path(".."), PathBuf::from("."));
+        assert_eq!(path("/repo/c/d.csv"), PathBuf::from("c/d.csv"));
+        assert_eq!(
+            config.work_path(Path::new("diff.sh")),
+            PathBuf::from("/repo/data/diff.sh")
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of the repository")]
+    fn test_repository_path_outside_of_the_root() {
+        config().repository_path(Path::new("../../elsewhere.csv"));
+    }
+}
@@ -14,6 +14,7 @@ mod vcs;
 mod cli;
 mod config;
 mod enums;
+mod schemas;
 mod utils;
 use cli::Cli;
 
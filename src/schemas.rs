@@ -0,0 +1,100 @@
+use libsql::Connection;
+
+/// Migrations of the root logbook in order. A released migration is never edited, changes go
+/// into a new one.
+pub const ROOT_MIGRATIONS: [&str; 4] = [
+    include_str!("../schemas/root/001_init.sql"),
+    include_str!("../schemas/root/002_branches.sql"),
+    include_str!("../schemas/root/003_tags.sql"),
+    include_str!("../schemas/root/004_commits.sql"),
+];
+
+/// Migrations of the logbook of every file
+pub const FILE_MIGRATIONS: [&str; 8] = [
+    include_str!("../schemas/file/001_init.sql"),
+    include_str!("../schemas/file/002_objects.sql"),
+    include_str!("../schemas/file/003_drop_files_trigger.sql"),
+    include_str!("../schemas/file/004_commit_timestamps.sql"),
+    include_str!("../schemas/file/005_diff_status.sql"),
+    include_str!("../schemas/file/006_file_metadata.sql"),
+    include_str!("../schemas/file/007_chunks.sql"),
+    include_str!("../schemas/file/008_root_commits.sql"),
+];
+
+/// Apply the migrations the logbook hasn't seen yet. Logbooks created before the schema was
+/// versioned are at version 0, the first migration only creating what they lack.
+pub async fn migrate(conn: &Connection, migrations: &[&str]) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL, applied_at INTEGER DEFAULT CURRENT_TIMESTAMP);",
+    )
+    .await
+    .expect("unable to create the schema version table");
+    let version = schema_version(conn).await;
+    for (version, migration) in migrations.iter().enumerate().skip(version as usize) {
+        conn.execute_batch(&format!(
+            "BEGIN;\n{migration}\nINSERT INTO schema_version (version) VALUES ({});\nCOMMIT;",
+            version + 1
+        ))
+        .await
+        .unwrap_or_else(|err| panic!("unable to apply the migration {}: {err}", version + 1));
+    }
+}
+
+pub async fn schema_version(conn: &Connection) -> u32 {
+    conn.query("SELECT COALESCE(MAX(version), 0) FROM schema_version", ())
+        .await
+        .expect("unable to read the schema version")
+        .next()
+        .await
+        .expect("iterator empty")
+        .expect("empyt")
+        .get::<u32>(0)
+        .expect("couldnt get the value")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libsql::Builder;
+
+    async fn has_trigger(conn: &Connection) -> bool {
+        conn.query(
+            "SELECT 1 FROM sqlite_master WHERE type='trigger' AND name='update_files_timestamp'",
+            (),
+        )
+        .await
+        .unwrap()
+        .next()
+        .await
+        .unwrap()
+        .is_some()
+    }
+
+    #[tokio::test]
+    async fn test_migrate_unversioned_logbook() {
+        let db = Builder::new_local(":memory:").build().await.unwrap();
+        let conn = db.connect().unwrap();
+        // Logbook created before the schema was versioned: the first migration is that
+        // schema as it was, trigger included
+        conn.execute_batch(FILE_MIGRATIONS[0]).await.unwrap();
+        assert!(has_trigger(&conn).await);
+
+        migrate(&conn, &FILE_MIGRATIONS).await;
+        assert!(!has_trigger(&conn).await);
+        assert_eq!(schema_version(&conn).await, FILE_MIGRATIONS.len() as u32);
+        conn.execute(
+            "INSERT INTO files (path, branch, author) VALUES ('a.csv', 'main', '')",
+            (),
+        )
+        .await
+        .unwrap();
+        // The trigger of the first schema made every update of a version fail
+        conn.execute("UPDATE files SET remote='s3' WHERE path='a.csv'", ())
+            .await
+            .unwrap();
+
+        // Nothing left to apply
+        migrate(&conn, &FILE_MIGRATIONS).await;
+        assert_eq!(schema_version(&conn).await, FILE_MIGRATIONS.len() as u32);
+    }
+}
@@ -0,0 +1,380 @@
+use super::{
+    cli::{current_branch, Events},
+    file::{FileFacadeFactory, Logbook},
+    versioning::{FileStatus, VersionRef},
+};
+use crate::{config::Config, enums::OutputFormat};
+
+use clap::{Args, Subcommand};
+use indicatif::ProgressBar;
+use serde::Serialize;
+use std::{fmt, path::PathBuf};
+
+/// Data branches live in the root logbook so they can exist without any git branch
+#[derive(Debug, Clone, Serialize)]
+pub struct Branch {
+    pub name: String,
+    pub description: String,
+    pub status: String,
+    pub author: String,
+    pub created_at: String,
+}
+
+impl fmt::Display for Branch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{}]", self.name, self.status)?;
+        if !self.author.is_empty() {
+            write!(f, " {}", self.author)?;
+        }
+        if !self.description.is_empty() {
+            write!(f, " - {}", self.description)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct BranchArgs {
+    #[command(subcommand)]
+    pub command: BranchCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BranchCommands {
+    /// Create a new branch starting from the latest versions of another one
+    #[command(arg_required_else_help = true)]
+    Create(CreateBranch),
+
+    /// List the branches
+    List(ListBranches),
+
+    /// Delete a branch. Its versions are kept in the logbooks
+    #[command(arg_required_else_help = true)]
+    Delete(DeleteBranch),
+
+    /// Use another branch and restore the working files with its latest versions
+    #[command(arg_required_else_help = true)]
+    Switch(SwitchBranch),
+
+    /// Bring the latest versions of a branch into another one
+    #[command(arg_required_else_help = true)]
+    Merge(MergeBranch),
+}
+
+impl BranchCommands {
+    pub async fn handle_commands(&self, config: Config) -> i16 {
+        match self {
+            BranchCommands::Create(args) => args.run(&config).await,
+            BranchCommands::List(args) => args.run(&config).await,
+            BranchCommands::Delete(args) => args.run(&config).await,
+            BranchCommands::Switch(args) => args.run(config).await,
+            BranchCommands::Merge(args) => args.run(&config).await,
+        }
+    }
+}
+
+/// Copy the latest version of every file tracked in source into target, recording a commit
+/// for the ones that changed
+async fn merge(config: &Config, root_logbook: &Logbook, source: &str, target: &str) {
+    let message = format!("Merge branch '{source}' into '{target}'");
+    let paths = root_logbook.files_tracked_in(source).await;
+    let files = FileFacadeFactory::new(
+        paths.into_iter().map(PathBuf::from).collect(),
+        target,
+        config,
+    );
+    for file in files {
+        let file = file.init().await.merge(source, &message).await;
+        if !file.has_changed() {
+            continue;
+        }
+        if !root_logbook.file_is_tracked(&file).await {
+            root_logbook.track_file(&file).await;
+        }
+        root_logbook.save_event(&file, &Events::Merge).await;
+    }
+}
+
+/// Files of the branch whose working copy differs from their latest version
+async fn modified_files(
+    config: &Config,
+    root_logbook: &Logbook,
+    branch: &str,
+) -> Vec<PathBuf> {
+    let paths = root_logbook.files_tracked_in(branch).await;
+    let files = FileFacadeFactory::new(
+        paths.into_iter().map(PathBuf::from).collect(),
+        branch,
+        config,
+    );
+    let mut modified = Vec::new();
+    for file in files {
+        let status = file.init().await.status(&ProgressBar::hidden()).await;
+        if status.status == FileStatus::Modified {
+            modified.push(status.path);
+        }
+    }
+    modified
+}
+
+/// Override the working files with the latest versions of the branch
+async fn restore_latest(config: &Config, root_logbook: &Logbook, branch: &str) {
+    let paths = root_logbook.files_tracked_in(branch).await;
+    let files = FileFacadeFactory::new(
+        paths.into_iter().map(PathBuf::from).collect(),
+        branch,
+        config,
+    )
+    .set_remote(config, &None, &None);
+    for file in files {
+        let file = file.init().await;
+        if file.previous_version().await.is_some() {
+            match file.checkout(&VersionRef::Latest).await {
+                Ok(file) => root_logbook.save_event(&file, &Events::Checkout).await,
+                Err(err) => eprintln!("{err}"),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct CreateBranch {
+    #[arg(short, long)]
+    name: String,
+
+    #[arg(short, long, default_value = "")]
+    description: String,
+
+    // Branch to start from. Defaults to the current one
+    #[arg(short, long, required = false)]
+    from: Option<String>,
+
+    // Start without any version
+    #[arg(short, long, default_value_t = false)]
+    empty: bool,
+}
+
+impl CreateBranch {
+    async fn run(&self, config: &Config) -> i16 {
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        if root_logbook.branch_exists(&self.name).await {
+            println!("Branch {} already exists", self.name);
+            return 1;
+        }
+        root_logbook
+            .create_branch(&self.name, &self.description, &config.author())
+            .await;
+        if !self.empty {
+            let from = match &self.from {
+                Some(from) => from.to_owned(),
+                None => current_branch(config).await,
+            };
+            merge(config, &root_logbook, &from, &self.name).await;
+        }
+        println!("Branch {} created", self.name);
+        0
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct ListBranches {
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+}
+
+impl ListBranches {
+    async fn run(&self, config: &Config) -> i16 {
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        let branches = root_logbook.branches().await;
+        match self.format {
+            OutputFormat::Human => {
+                let current = current_branch(config).await;
+                for branch in branches {
+                    let marker = if branch.name == current { "*" } else { " " };
+                    println!("{marker} {branch}");
+                }
+            },
+            OutputFormat::Json => {
+                println!("{}", serde_json::to_string_pretty(&branches).unwrap())
+            },
+        }
+        0
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct DeleteBranch {
+    #[arg(short, long)]
+    name: String,
+}
+
+impl DeleteBranch {
+    async fn run(&self, config: &Config) -> i16 {
+        if self.name == current_branch(config).await {
+            println!("Cannot delete {}, it is the current branch", self.name);
+            return 1;
+        }
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        if !root_logbook.branch_exists(&self.name).await {
+            println!("Branch {} doesn't exist", self.name);
+            return 1;
+        }
+        root_logbook
+            .delete_branch(&self.name, &config.author())
+            .await;
+        println!("Branch {} deleted", self.name);
+        0
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct SwitchBranch {
+    #[arg(short, long)]
+    name: String,
+}
+
+impl SwitchBranch {
+    async fn run(&self, mut config: Config) -> i16 {
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        if !root_logbook.branch_exists(&self.name).await {
+            println!("Branch {} doesn't exist, create it first", self.name);
+            return 1;
+        }
+        let current = current_branch(&config).await;
+        let modified = modified_files(&config, &root_logbook, &current).await;
+        if !modified.is_empty() {
+            println!("Commit your changes before switching branches:");
+            modified
+                .iter()
+                .for_each(|p| println!("    {}", p.display()));
+            return 1;
+        }
+        config.set_branch(&self.name).save();
+        restore_latest(&config, &root_logbook, &self.name).await;
+        println!("Switched to branch {}", self.name);
+        0
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct MergeBranch {
+    // Branch to merge
+    #[arg(short, long)]
+    name: String,
+
+    // Branch receiving the versions. Defaults to the current one
+    #[arg(short, long, required = false)]
+    into: Option<String>,
+}
+
+impl MergeBranch {
+    async fn run(&self, config: &Config) -> i16 {
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        let current = current_branch(config).await;
+        let target = self.into.clone().unwrap_or(current.clone());
+        if target == current {
+            let modified = modified_files(config, &root_logbook, &current).await;
+            if !modified.is_empty() {
+                println!("Commit your changes before merging:");
+                modified
+                    .iter()
+                    .for_each(|p| println!("    {}", p.display()));
+                return 1;
+            }
+        }
+        merge(config, &root_logbook, &self.name, &target).await;
+        root_logbook.set_branch_status(&self.name, "Merged").await;
+
+        if target == current {
+            restore_latest(config, &root_logbook, &target).await;
+        }
+        println!("Branch {} merged into {}", self.name, target);
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcs::testing::Repository;
+
+    async fn branch(repository: &Repository, name: &str) -> Option<Branch> {
+        Logbook::local(&repository.config().local_db())
+            .await
+            .branches()
+            .await
+            .into_iter()
+            .find(|b| b.name == name)
+    }
+
+    #[tokio::test]
+    async fn test_switch_and_merge_branches() {
+        let repository = Repository::new("branch-switch").await;
+        repository.write("data.csv", "name\nfirst\n").await;
+        assert_eq!(repository.run(&["add", "-p", "data.csv"]).await, 0);
+        assert_eq!(repository.run(&["branch", "create", "-n", "dev"]).await, 0);
+        assert_eq!(repository.run(&["branch", "switch", "-n", "dev"]).await, 0);
+        assert_eq!(repository.config().branch().as_deref(), Some("dev"));
+
+        repository.write("data.csv", "name\nfirst\nsecond\n").await;
+        assert_eq!(
+            repository
+                .run(&["commit", "-p", "data.csv", "-m", "second"])
+                .await,
+            0
+        );
+        assert_eq!(repository.run(&["branch", "switch", "-n", "main"]).await, 0);
+        assert_eq!(repository.read("data.csv").await, "name\nfirst\n");
+
+        // Uncommitted changes would be lost
+        repository.write("data.csv", "name\nuncommitted\n").await;
+        assert_eq!(repository.run(&["branch", "switch", "-n", "dev"]).await, 1);
+        assert_eq!(repository.run(&["branch", "merge", "-n", "dev"]).await, 1);
+        assert_eq!(repository.config().branch().as_deref(), Some("main"));
+        repository.write("data.csv", "name\nfirst\n").await;
+
+        assert_eq!(repository.run(&["branch", "merge", "-n", "dev"]).await, 0);
+        assert_eq!(repository.read("data.csv").await, "name\nfirst\nsecond\n");
+        assert_eq!(branch(&repository, "dev").await.unwrap().status, "Merged");
+    }
+
+    #[tokio::test]
+    async fn test_deleted_branch_versions_survive_gc() {
+        let repository = Repository::new("branch-delete").await;
+        repository.write("data.csv", "name\nfirst\n").await;
+        assert_eq!(repository.run(&["add", "-p", "data.csv"]).await, 0);
+        assert_eq!(
+            repository
+                .run(&["branch", "create", "-n", "dev", "--empty"])
+                .await,
+            0
+        );
+        assert_eq!(repository.run(&["branch", "switch", "-n", "dev"]).await, 0);
+        // Only tracked in the branch about to be deleted
+        repository.write("dev.csv", "name\ndev\n").await;
+        assert_eq!(repository.run(&["add", "-p", "dev.csv"]).await, 0);
+        assert_eq!(repository.run(&["branch", "switch", "-n", "main"]).await, 0);
+        assert_eq!(repository.run(&["branch", "delete", "-n", "main"]).await, 1);
+
+        assert_eq!(repository.run(&["branch", "delete", "-n", "dev"]).await, 0);
+        assert!(branch(&repository, "dev").await.is_none());
+        assert_eq!(repository.run(&["branch", "delete", "-n", "dev"]).await, 1);
+        assert_eq!(repository.run(&["gc", "--keep-last", "1"]).await, 0);
+
+        tokio::fs::remove_file(repository.dir.join("dev.csv"))
+            .await
+            .unwrap();
+        let now = chrono::offset::Local::now().timestamp().to_string();
+        assert_eq!(
+            repository
+                .run(&["checkout", "-p", "dev.csv", "-b", "dev", "-t", &now])
+                .await,
+            0
+        );
+        assert_eq!(repository.read("dev.csv").await, "name\ndev\n");
+
+        // Creating it again brings it back
+        assert_eq!(repository.run(&["branch", "create", "-n", "dev"]).await, 0);
+        assert_eq!(branch(&repository, "dev").await.unwrap().status, "Active");
+    }
+}
@@ -1,19 +1,35 @@
 use super::{
-    comparaison::ComparaisonTechnique,
+    branch::BranchArgs,
+    comparaison::{Comparaison, ComparaisonTechnique},
     file::{FileFacade, FileFacadeFactory, Logbook},
+    gc::Gc,
+    ignores::IgnoreRules,
+    objects::{hash_concurrency, hashing_progress},
+    pathspec::{is_under, PathSpec},
+    tag::{tagged_object, TagArgs},
+    versioning::{
+        get_latest_git_commit, CommitGroup, FileStatus, LogFilter, StatusEntry,
+        VersionRef,
+    },
 };
 use crate::{
     config::{Config, PushStrategy, Storage},
-    enums::ColorWhen,
+    enums::{ColorWhen, OutputFormat},
 };
 
 use futures::{stream::FuturesUnordered, StreamExt};
+use indicatif::{MultiProgress, ProgressBar};
+use serde_json::json;
 use std::process::Stdio;
-use std::{fmt, path::PathBuf};
+use std::{
+    collections::{BTreeSet, HashSet},
+    env, fmt,
+    path::{Path, PathBuf},
+};
 use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
 
-use clap::{Args, Subcommand};
+use clap::{ArgGroup, Args, Subcommand};
 
 #[derive(Debug)]
 pub enum Events {
@@ -22,6 +38,9 @@ pub enum Events {
     Pull,
     Push,
     Remove,
+    Checkout,
+    Merge,
+    Gc,
 }
 
 impl fmt::Display for Events {
@@ -70,11 +89,47 @@ pub enum VcsCommands {
     Pull(Pull),
 
     Show(Show),
+
+    /// Restore files to a recorded version
+    #[command(arg_required_else_help = true)]
+    Checkout(Checkout),
+
+    /// Show the commits of one or more files, or of the whole repository
+    Log(Log),
+
+    /// Show which tracked files changed since their last commit
+    Status(Status),
+
+    /// Compare two versions of a file
+    #[command(arg_required_else_help = true)]
+    Diff(Diff),
+
+    /// Create, list, delete, switch and merge data branches
+    #[command(arg_required_else_help = true)]
+    Branch(BranchArgs),
+
+    /// Remove the old versions from the history, and optionally from the remote
+    #[command(arg_required_else_help = true)]
+    Gc(Gc),
+
+    /// Create, list, show and delete named snapshots of many files
+    #[command(arg_required_else_help = true)]
+    Tag(TagArgs),
 }
 
 impl VcsCommands {
     pub async fn handle_commands(&self) -> i16 {
-        let config = Config::new();
+        let config = match Config::new() {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("{err}");
+                return 1;
+            },
+        };
+        // Tracked paths are relative to the repository root, work from there like git does.
+        // The paths given on the command line are made relative to it by the commands.
+        env::set_current_dir(config.root_dir())
+            .expect("unable to move to the repository root");
         match self {
             VcsCommands::Add(args) => args.run(&config).await,
             VcsCommands::Commit(args) => args.run(&config).await,
@@ -82,8 +137,22 @@ impl VcsCommands {
             VcsCommands::Pull(args) => args.run(&config).await,
             VcsCommands::Remove(args) => args.run(&config).await,
             VcsCommands::Show(args) => args.run(&config).await,
-        };
-        0
+            VcsCommands::Checkout(args) => args.run(&config).await,
+            VcsCommands::Log(args) => args.run(&config).await,
+            VcsCommands::Status(args) => args.run(&config).await,
+            VcsCommands::Diff(args) => args.run(&config).await,
+            VcsCommands::Branch(args) => args.command.handle_commands(config).await,
+            VcsCommands::Gc(args) => args.run(&config).await,
+            VcsCommands::Tag(args) => args.command.handle_commands(&config).await,
+        }
+    }
+}
+
+/// The data branch set with `vcs branch switch`, or the git one if none was set
+pub async fn current_branch(config: &Config) -> String {
+    match config.branch() {
+        Some(branch) => branch,
+        None => get_git_branch().await,
     }
 }
 
@@ -102,22 +171,118 @@ async fn get_git_branch() -> String {
     }
 }
 
+/// Accept either a timestamp or a date like 2024-03-21
+fn parse_date(value: &str) -> Result<i64, String> {
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|err| format!("{err}, expected a timestamp or a YYYY-MM-DD date"))?
+        .and_hms_opt(0, 0, 0)
+        .and_then(|d| d.and_local_timezone(chrono::Local).earliest())
+        .map(|d| d.timestamp())
+        .ok_or(format!("{value} is not a valid local date"))
+}
+
+/// Accept a number of seconds or a duration like 30m, 12h, 7d or 4w
+pub fn parse_duration(value: &str) -> Result<i64, String> {
+    let (amount, unit) =
+        value.split_at(value.trim_end_matches(char::is_alphabetic).len());
+    let amount = amount
+        .parse::<i64>()
+        .map_err(|err| format!("{err}, expected a duration like 7d"))?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("unknown unit {unit}, expected s, m, h, d or w")),
+    };
+    Ok(amount * seconds)
+}
+
+/// Paths recorded by the tag, limited to the given ones if any. Without tag the paths are
+/// used as they are.
+async fn tagged_paths(
+    config: &Config,
+    tag: &Option<String>,
+    paths: &[PathBuf],
+) -> Result<Vec<PathBuf>, String> {
+    let Some(tag) = tag else {
+        return Ok(paths.to_vec());
+    };
+    let root_logbook = Logbook::local(&config.local_db()).await;
+    if !root_logbook.tag_exists(tag).await {
+        return Err(format!("Tag {tag} doesn't exist"));
+    }
+    let versions = root_logbook.tag_versions(tag).await;
+    Ok(select_paths(versions, paths))
+}
+
+/// Paths changed by the repository commit, limited to the given ones if any
+async fn committed_paths(
+    config: &Config,
+    id: u32,
+    paths: &[PathBuf],
+) -> Result<Vec<PathBuf>, String> {
+    let versions = Logbook::local(&config.local_db())
+        .await
+        .commit_files(id)
+        .await;
+    if versions.is_empty() {
+        return Err(format!("No commit {id}"));
+    }
+    Ok(select_paths(versions, paths))
+}
+
+fn select_paths(versions: Vec<(PathBuf, String)>, paths: &[PathBuf]) -> Vec<PathBuf> {
+    versions
+        .into_iter()
+        .map(|(path, _)| path)
+        .filter(|p| paths.is_empty() || paths.iter().any(|s| is_under(p, s)))
+        .collect()
+}
+
 trait Vcs {
+    /// Handle the files concurrently. The exit code is 1 when any of them failed
     async fn run(&self, config: &Config) -> i16 {
-        let files = self.get_files_factory(config).await;
+        let files = match self.get_files_factory(config).await {
+            Ok(files) => files,
+            Err(err) => {
+                eprintln!("{err}");
+                return 1;
+            },
+        };
         let root_logbook = Logbook::local(&config.local_db()).await;
         let tasks: FuturesUnordered<_> = FuturesUnordered::new();
         for file in files {
             tasks.push(self.initialize_file_facade(file, &root_logbook));
         }
-        let _: Vec<()> = tasks.collect().await;
-        0
+        let results: Vec<Result<(), String>> = tasks.collect().await;
+        let mut code = 0;
+        for err in results.into_iter().filter_map(Result::err) {
+            eprintln!("{err}");
+            code = 1;
+        }
+        code
     }
-    async fn get_files_factory(&self, config: &Config) -> FileFacadeFactory;
-    async fn handle_file_facade(&self, file: FileFacade, root_logbook: &Logbook);
-    async fn initialize_file_facade(&self, file: FileFacade, root_logbook: &Logbook) {
+    async fn get_files_factory(
+        &self,
+        config: &Config,
+    ) -> Result<FileFacadeFactory, String>;
+    async fn handle_file_facade(
+        &self,
+        file: FileFacade,
+        root_logbook: &Logbook,
+    ) -> Result<(), String>;
+    async fn initialize_file_facade(
+        &self,
+        file: FileFacade,
+        root_logbook: &Logbook,
+    ) -> Result<(), String> {
         self.handle_file_facade(file.init().await, root_logbook)
-            .await;
+            .await
     }
 }
 
@@ -125,34 +290,44 @@ trait Vcs {
 //time or do we want to allow the branch to be passed when doing an operation or with git_sync
 #[derive(Debug, Args, Clone)]
 pub struct Add {
-    #[arg(short, long,num_args = 1..)]
-    paths: Vec<PathBuf>,
+    #[command(flatten)]
+    paths: PathSpec,
 
     #[arg(short, long, required = false)]
     branch: Option<String>,
 }
 
 impl Vcs for Add {
-    async fn get_files_factory(&self, config: &Config) -> FileFacadeFactory {
-        FileFacadeFactory::new(
-            self.paths.clone(),
-            self.branch.as_ref().unwrap_or(&get_git_branch().await),
+    async fn get_files_factory(
+        &self,
+        config: &Config,
+    ) -> Result<FileFacadeFactory, String> {
+        Ok(FileFacadeFactory::new(
+            self.paths.resolve(config).await,
+            self.branch
+                .as_ref()
+                .unwrap_or(&current_branch(config).await),
             config,
-        )
+        ))
     }
-    async fn handle_file_facade(&self, file: FileFacade, root_logbook: &Logbook) {
+    async fn handle_file_facade(
+        &self,
+        file: FileFacade,
+        root_logbook: &Logbook,
+    ) -> Result<(), String> {
         if !root_logbook.file_is_tracked(&file).await {
             let file = file.add().await;
             root_logbook.track_file(&file).await;
             root_logbook.save_event(&file, &Events::Add).await;
         };
+        Ok(())
     }
 }
 
 #[derive(Debug, Args, Clone)]
 pub struct Commit {
-    #[arg(short, long, num_args = 1..)]
-    paths: Vec<PathBuf>,
+    #[command(flatten)]
+    paths: PathSpec,
 
     #[arg(short, long, required = false)]
     branch: Option<String>,
@@ -163,32 +338,114 @@ pub struct Commit {
     #[arg(short, long, default_value = "smart", value_enum)]
     comparaison: ComparaisonTechnique,
 
-    // Path to the script to execute when the comparaison technique is Custom
-    #[arg(short, long, required = false)]
+    // Path to the script to execute when the comparaison technique is Custom. It receives the
+    // previous and the current version paths and must print a json to stdout
+    #[arg(short, long, required_if_eq("comparaison", "custom"))]
     script: Option<PathBuf>,
+
+    // Seconds given to the custom script before considering the comparaison failed
+    #[arg(long, default_value_t = 60)]
+    timeout: u64,
+
+    // Column identifying the rows when comparing tabular files
+    #[arg(short, long, required = false)]
+    key: Option<String>,
+
+    // Summarise the changes of the notebook cells outputs
+    #[arg(long, default_value_t = false)]
+    outputs: bool,
 }
-impl Vcs for Commit {
-    async fn get_files_factory(&self, config: &Config) -> FileFacadeFactory {
-        FileFacadeFactory::new(
-            self.paths.clone(),
-            self.branch.as_ref().unwrap_or(&get_git_branch().await),
-            config,
-        )
-        .set_message(&self.message)
-        .set_comparaison(&self.comparaison, &self.script)
-    }
-    async fn handle_file_facade(&self, file: FileFacade, root_logbook: &Logbook) {
-        if file.compare(&self.message).await.has_changed() {
-            //TODO: save the comparaison results
-            root_logbook.save_event(&file, &Events::Commit).await;
+impl Commit {
+    /// Commit every changed file under a single repository commit. If any of them fails
+    /// nothing is recorded.
+    async fn run(&self, config: &Config) -> i16 {
+        let branch = match &self.branch {
+            Some(branch) => branch.clone(),
+            None => current_branch(config).await,
+        };
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        let tasks: FuturesUnordered<_> = FuturesUnordered::new();
+        let progress = MultiProgress::new();
+        for file in self
+            .get_files_factory(config, &branch)
+            .await
+            .set_multi_progress(&progress)
+        {
+            tasks.push(async move { file.init().await.prepare(&self.message).await });
+        }
+        let prepared: Vec<Result<FileFacade, String>> = tasks.collect().await;
+        let mut files = Vec::new();
+        let mut failed = false;
+        for file in prepared {
+            match file {
+                Ok(file) if file.has_changed() => files.push(file),
+                Ok(_) => {},
+                Err(err) => {
+                    eprintln!("{err}");
+                    failed = true;
+                },
+            }
+        }
+        if failed {
+            eprintln!("Commit aborted, nothing was recorded");
+            return 1;
         }
+        if files.is_empty() {
+            println!("Nothing to commit");
+            return 0;
+        }
+
+        let id = match root_logbook
+            .begin_commit(
+                files[0].file().timestamp(),
+                &self.message,
+                &branch,
+                &config.author(),
+                &get_latest_git_commit().await,
+            )
+            .await
+        {
+            Ok(id) => id,
+            Err(err) => {
+                eprintln!("{err}");
+                return 1;
+            },
+        };
+        if let Err(err) = root_logbook.record_commit(id, &mut files).await {
+            eprintln!("{err}\nCommit aborted, nothing was recorded");
+            return 1;
+        }
+        root_logbook.complete_commit(id).await;
+        for file in &files {
+            root_logbook.save_event(file, &Events::Commit).await;
+        }
+        println!("commit {id}: {} files changed", files.len());
+        0
+    }
+
+    async fn get_files_factory(
+        &self,
+        config: &Config,
+        branch: &str,
+    ) -> FileFacadeFactory {
+        FileFacadeFactory::new(self.paths.resolve(config).await, branch, config)
+            .set_message(&self.message)
+            .set_comparaison(
+                Comparaison::new(
+                    &self.comparaison,
+                    &self.script.as_deref().map(|s| config.work_path(s)),
+                )
+                .set_timeout(self.timeout)
+                .set_key(&self.key)
+                .set_outputs(self.outputs),
+            )
     }
 }
 
 #[derive(Debug, Args, Clone)]
 pub struct Push {
-    #[arg(short, long, num_args = 1..)]
-    paths: Vec<PathBuf>,
+    #[command(flatten)]
+    paths: PathSpec,
 
     #[arg(short, long, required = false)]
     branch: Option<String>,
@@ -203,24 +460,34 @@ pub struct Push {
     compress: bool,
 }
 impl Vcs for Push {
-    async fn get_files_factory(&self, config: &Config) -> FileFacadeFactory {
-        FileFacadeFactory::new(
-            self.paths.clone(),
-            self.branch.as_ref().unwrap_or(&get_git_branch().await),
+    async fn get_files_factory(
+        &self,
+        config: &Config,
+    ) -> Result<FileFacadeFactory, String> {
+        Ok(FileFacadeFactory::new(
+            self.paths.resolve(config).await,
+            self.branch
+                .as_ref()
+                .unwrap_or(&current_branch(config).await),
             config,
         )
-        .set_remote(config, &self.remote, &self.strategy)
+        .set_remote(config, &self.remote, &self.strategy))
     }
-    async fn handle_file_facade(&self, file: FileFacade, root_logbook: &Logbook) {
-        let file = file.push().await;
+    async fn handle_file_facade(
+        &self,
+        file: FileFacade,
+        root_logbook: &Logbook,
+    ) -> Result<(), String> {
+        let file = file.push().await?;
         root_logbook.save_event(&file, &Events::Push).await;
+        Ok(())
     }
 }
 
 #[derive(Debug, Args, Clone)]
 pub struct Remove {
-    #[arg(short, long, num_args = 1..)]
-    paths: Vec<PathBuf>,
+    #[command(flatten)]
+    paths: PathSpec,
 
     #[arg(short, long, required = false)]
     branch: Option<String>,
@@ -241,44 +508,158 @@ pub struct Remove {
 }
 
 impl Vcs for Remove {
-    async fn get_files_factory(&self, config: &Config) -> FileFacadeFactory {
-        FileFacadeFactory::new(
-            self.paths.clone(),
-            self.branch.as_ref().unwrap_or(&get_git_branch().await),
+    async fn get_files_factory(
+        &self,
+        config: &Config,
+    ) -> Result<FileFacadeFactory, String> {
+        Ok(FileFacadeFactory::new(
+            self.paths.resolve(config).await,
+            self.branch
+                .as_ref()
+                .unwrap_or(&current_branch(config).await),
             config,
         )
-        .set_remote(config, &self.remote, &None)
+        .set_remote(config, &self.remote, &None))
     }
-    async fn handle_file_facade(&self, file: FileFacade, root_logbook: &Logbook) {
-        let file = file.remove().await;
+    async fn handle_file_facade(
+        &self,
+        file: FileFacade,
+        root_logbook: &Logbook,
+    ) -> Result<(), String> {
+        let file = file.remove().await?;
         root_logbook.save_event(&file, &Events::Remove).await;
+        Ok(())
     }
 }
 
 #[derive(Debug, Args, Clone)]
 pub struct Pull {
-    #[arg(short, long, num_args = 1..)]
-    paths: Vec<PathBuf>,
+    #[command(flatten)]
+    paths: PathSpec,
 
     #[arg(short, long, required = false)]
     branch: Option<String>,
 
     #[arg(short, long, value_enum)]
     remote: Option<Storage>,
+
+    // Pull every version recorded by the tag instead of the latest ones
+    #[arg(long, required = false)]
+    tag: Option<String>,
 }
 
 impl Vcs for Pull {
-    async fn get_files_factory(&self, config: &Config) -> FileFacadeFactory {
-        FileFacadeFactory::new(
-            self.paths.clone(),
-            self.branch.as_ref().unwrap_or(&get_git_branch().await),
+    async fn get_files_factory(
+        &self,
+        config: &Config,
+    ) -> Result<FileFacadeFactory, String> {
+        let paths = match &self.tag {
+            Some(_) => self
+                .paths
+                .select(config, tagged_paths(config, &self.tag, &[]).await?),
+            None => self.paths.resolve(config).await,
+        };
+        Ok(FileFacadeFactory::new(
+            paths,
+            self.branch
+                .as_ref()
+                .unwrap_or(&current_branch(config).await),
             config,
         )
-        .set_remote(config, &self.remote, &None)
+        .set_remote(config, &self.remote, &None))
     }
-    async fn handle_file_facade(&self, file: FileFacade, root_logbook: &Logbook) {
-        let file = file.pull().await;
+    async fn handle_file_facade(
+        &self,
+        file: FileFacade,
+        root_logbook: &Logbook,
+    ) -> Result<(), String> {
+        let object = match &self.tag {
+            Some(tag) => Some(tagged_object(root_logbook, tag, &file).await?),
+            None => None,
+        };
+        let file = file.pull(object).await?;
+        if !root_logbook.file_is_tracked(&file).await {
+            root_logbook.track_file(&file).await;
+        }
         root_logbook.save_event(&file, &Events::Pull).await;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+#[command(group(ArgGroup::new("version").required(true)))]
+pub struct Checkout {
+    #[arg(short, long, num_args = 1..)]
+    paths: Vec<PathBuf>,
+
+    #[arg(short, long, required = false)]
+    branch: Option<String>,
+
+    // Id of the repository commit, every file it changed is restored
+    #[arg(short, long, group = "version")]
+    commit: Option<u32>,
+
+    // Restore the version that was the latest at this time
+    #[arg(short, long, group = "version")]
+    timestamp: Option<i64>,
+
+    // Restore the version commited alongside this git commit. Short hashes are allowed
+    #[arg(short, long, group = "version")]
+    git_commit: Option<String>,
+
+    // Restore the whole snapshot recorded by the tag
+    #[arg(long, group = "version")]
+    tag: Option<String>,
+
+    // Where to look for the version if it's not in the local history anymore
+    #[arg(short, long, value_enum, required = false)]
+    remote: Option<Storage>,
+}
+
+impl Checkout {
+    fn version(&self) -> VersionRef {
+        match (&self.commit, &self.timestamp, &self.git_commit) {
+            (Some(commit), _, _) => VersionRef::Commit(*commit),
+            (_, Some(timestamp), _) => VersionRef::Timestamp(*timestamp),
+            (_, _, Some(git_commit)) => VersionRef::GitCommit(git_commit.to_owned()),
+            _ => VersionRef::Latest,
+        }
+    }
+}
+
+impl Vcs for Checkout {
+    async fn get_files_factory(
+        &self,
+        config: &Config,
+    ) -> Result<FileFacadeFactory, String> {
+        let selected = config.repository_paths(&self.paths);
+        let paths = match self.commit {
+            Some(id) => committed_paths(config, id, &selected).await?,
+            None => tagged_paths(config, &self.tag, &selected).await?,
+        };
+        Ok(FileFacadeFactory::new(
+            paths,
+            self.branch
+                .as_ref()
+                .unwrap_or(&current_branch(config).await),
+            config,
+        )
+        .set_remote(config, &self.remote, &None))
+    }
+    async fn handle_file_facade(
+        &self,
+        file: FileFacade,
+        root_logbook: &Logbook,
+    ) -> Result<(), String> {
+        let version = match &self.tag {
+            Some(tag) => {
+                VersionRef::Object(tagged_object(root_logbook, tag, &file).await?)
+            },
+            None => self.version(),
+        };
+        let file = file.checkout(&version).await?;
+        root_logbook.save_event(&file, &Events::Checkout).await;
+        Ok(())
     }
 }
 
@@ -311,3 +692,341 @@ impl Show {
         child.wait().await.unwrap();
     }
 }
+
+#[derive(Debug, Args, Clone)]
+pub struct Log {
+    // Files or directories to show. Every tracked file if none is given
+    #[arg(short, long, num_args = 0..)]
+    paths: Vec<PathBuf>,
+
+    #[arg(short, long, required = false)]
+    branch: Option<String>,
+
+    #[arg(short, long, required = false)]
+    author: Option<String>,
+
+    // Only commits after this timestamp or date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    since: Option<i64>,
+
+    // Only commits before this timestamp or date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    until: Option<i64>,
+
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+}
+
+impl Log {
+    async fn run(&self, config: &Config) -> i16 {
+        let paths = if self.paths.is_empty() {
+            let root_logbook = Logbook::local(&config.local_db()).await;
+            root_logbook
+                .files_tracked()
+                .await
+                .into_iter()
+                .collect::<BTreeSet<String>>()
+                .into_iter()
+                .map(PathBuf::from)
+                .collect()
+        } else {
+            config.repository_paths(&self.paths)
+        };
+        let filter = LogFilter {
+            branch: self.branch.clone(),
+            author: self.author.clone(),
+            since: self.since,
+            until: self.until,
+        };
+        let mut entries = Vec::new();
+        let files = FileFacadeFactory::new(
+            paths,
+            &self.branch.clone().unwrap_or_default(),
+            config,
+        );
+        for file in files {
+            if file.has_logbook() {
+                entries.extend(file.init().await.log(&filter).await);
+            }
+        }
+        entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        let commits = CommitGroup::group(entries);
+        match self.format {
+            OutputFormat::Human => commits.iter().for_each(|c| println!("{c}")),
+            OutputFormat::Json => {
+                println!("{}", serde_json::to_string_pretty(&commits).unwrap())
+            },
+        }
+        0
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct Status {
+    // Limit the status to these files or directories
+    #[arg(short, long, num_args = 0..)]
+    paths: Vec<PathBuf>,
+
+    #[arg(short, long, required = false)]
+    branch: Option<String>,
+
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+}
+
+impl Status {
+    async fn run(&self, config: &Config) -> i16 {
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        let selected = config.repository_paths(&self.paths);
+        let tracked: BTreeSet<PathBuf> = root_logbook
+            .files_tracked()
+            .await
+            .into_iter()
+            .map(PathBuf::from)
+            .filter(|p| Self::is_selected(&selected, p))
+            .collect();
+        let files = FileFacadeFactory::new(
+            tracked.iter().cloned().collect(),
+            self.branch
+                .as_ref()
+                .unwrap_or(&current_branch(config).await),
+            config,
+        );
+        let mut facades = Vec::new();
+        for file in files {
+            // Opening the logbook would create it, so leave alone files without one
+            facades.push(match file.has_logbook() {
+                true => file.init().await,
+                false => file,
+            });
+        }
+        let progress = match self.format {
+            OutputFormat::Human => hashing_progress(
+                tracked
+                    .iter()
+                    .filter_map(|p| p.metadata().ok())
+                    .map(|m| m.len())
+                    .sum(),
+            ),
+            OutputFormat::Json => ProgressBar::hidden(),
+        };
+        // Files are hashed concurrently, keeping their order
+        let mut entries: Vec<StatusEntry> = futures::stream::iter(facades.iter_mut())
+            .map(|file| file.status(&progress))
+            .buffered(hash_concurrency())
+            .collect()
+            .await;
+        progress.finish_and_clear();
+        let mut ignores = IgnoreRules::new(config.root_dir(), config.ignore_patterns());
+        entries.extend(Self::untracked(&selected, &tracked, &mut ignores));
+
+        match self.format {
+            OutputFormat::Human => entries
+                .iter()
+                .filter(|e| e.status != FileStatus::Unchanged || e.unpushed > 0)
+                .for_each(|e| println!("{e}")),
+            OutputFormat::Json => {
+                println!("{}", serde_json::to_string_pretty(&entries).unwrap())
+            },
+        }
+        0
+    }
+
+    fn is_selected(selected: &[PathBuf], path: &Path) -> bool {
+        selected.is_empty() || selected.iter().any(|p| is_under(path, p))
+    }
+
+    /// Files living in the same directories as tracked files but never added
+    fn untracked(
+        selected: &[PathBuf],
+        tracked: &BTreeSet<PathBuf>,
+        ignores: &mut IgnoreRules,
+    ) -> Vec<StatusEntry> {
+        let dirs: HashSet<&Path> = tracked
+            .iter()
+            .map(|p| p.parent().unwrap_or(Path::new("")))
+            .collect();
+        let mut untracked: Vec<StatusEntry> = dirs
+            .into_iter()
+            .filter_map(|dir| {
+                let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+                dir.read_dir().ok()
+            })
+            .flat_map(|entries| entries.filter_map(|e| e.ok()))
+            .map(|e| e.path())
+            .map(|p| p.strip_prefix("./").map(Path::to_path_buf).unwrap_or(p))
+            .filter(|p| {
+                p.is_file() && !tracked.contains(p) && Self::is_selected(selected, p)
+            })
+            .filter(|p| !ignores.is_ignored(p))
+            .map(|path| StatusEntry {
+                path,
+                status: FileStatus::Untracked,
+                unpushed: 0,
+            })
+            .collect();
+        untracked.sort_by(|a, b| a.path.cmp(&b.path));
+        untracked
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct Diff {
+    #[arg(short, long, num_args = 1..)]
+    paths: Vec<PathBuf>,
+
+    #[arg(short, long, required = false)]
+    branch: Option<String>,
+
+    // Version to compare from: working, latest, a commit id, @<timestamp> or a git commit
+    #[arg(long, default_value = "latest")]
+    from: VersionRef,
+
+    // Version to compare to: working, latest, a commit id, @<timestamp> or a git commit
+    #[arg(long, default_value = "working")]
+    to: VersionRef,
+
+    #[arg(short, long, default_value = "smart", value_enum)]
+    comparaison: ComparaisonTechnique,
+
+    // Path to the script to execute when the comparaison technique is Custom. It receives the
+    // previous and the current version paths and must print a json to stdout
+    #[arg(short, long, required_if_eq("comparaison", "custom"))]
+    script: Option<PathBuf>,
+
+    // Seconds given to the custom script before considering the comparaison failed
+    #[arg(long, default_value_t = 60)]
+    timeout: u64,
+
+    // Column identifying the rows when comparing tabular files
+    #[arg(short, long, required = false)]
+    key: Option<String>,
+
+    // Summarise the changes of the notebook cells outputs
+    #[arg(long, default_value_t = false)]
+    outputs: bool,
+
+    // Show the result saved when the versions were commited instead of comparing again
+    #[arg(long, default_value_t = false)]
+    stored: bool,
+
+    #[arg(short, long, value_enum, required = false)]
+    remote: Option<Storage>,
+
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+}
+
+impl Diff {
+    async fn run(&self, config: &Config) -> i16 {
+        let files = FileFacadeFactory::new(
+            config.repository_paths(&self.paths),
+            self.branch
+                .as_ref()
+                .unwrap_or(&current_branch(config).await),
+            config,
+        )
+        .set_remote(config, &self.remote, &None)
+        .set_comparaison(
+            Comparaison::new(
+                &self.comparaison,
+                &self.script.as_deref().map(|s| config.work_path(s)),
+            )
+            .set_timeout(self.timeout)
+            .set_key(&self.key)
+            .set_outputs(self.outputs),
+        );
+        let mut code = 0;
+        for file in files {
+            let file = file.init().await;
+            let (from, to) =
+                match (file.resolve(&self.from).await, file.resolve(&self.to).await) {
+                    (Ok(from), Ok(to)) => (from, to),
+                    (Err(err), _) | (_, Err(err)) => {
+                        eprintln!("{err}");
+                        code = 1;
+                        continue;
+                    },
+                };
+            let (technique, result) = match self.stored {
+                true => match file.stored_diff(&from, &to).await {
+                    Some(stored) => stored,
+                    None => {
+                        eprintln!("No stored diff for {:?}", file.path());
+                        code = 1;
+                        continue;
+                    },
+                },
+                false => {
+                    let diff = file.diff(&from, &to).await;
+                    (diff.technique().to_string(), diff.diff_result().clone())
+                },
+            };
+            match self.format {
+                OutputFormat::Human => println!(
+                    "{} {:?}..{:?} ({})\n{}",
+                    file.path().display(),
+                    self.from,
+                    self.to,
+                    technique,
+                    serde_json::to_string_pretty(&result).unwrap()
+                ),
+                OutputFormat::Json => println!(
+                    "{}",
+                    json!({
+                        "path": file.path(),
+                        "from": from.file().object(),
+                        "to": to.file().object(),
+                        "technique": technique,
+                        "result": result,
+                    })
+                ),
+            }
+        }
+        code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::Config, vcs::testing::Repository};
+
+    #[tokio::test]
+    async fn test_exit_codes() {
+        let repository = Repository::new("exit-codes").await;
+        repository.write("data.csv", "a\n1\n").await;
+        assert_eq!(repository.run(&["add", "-p", "data.csv"]).await, 0);
+        repository.write("data.csv", "a\n2\n").await;
+        assert_eq!(
+            repository
+                .run(&["commit", "-p", "data.csv", "-m", "second"])
+                .await,
+            0
+        );
+        assert_eq!(repository.run(&["checkout", "-c", "1"]).await, 0);
+        assert_eq!(
+            repository
+                .run(&["diff", "-p", "data.csv", "--from", "1"])
+                .await,
+            0
+        );
+
+        assert_eq!(repository.run(&["checkout", "-c", "42"]).await, 1);
+        assert_eq!(repository.run(&["checkout", "--tag", "missing"]).await, 1);
+        assert_eq!(repository.run(&["pull", "--tag", "missing"]).await, 1);
+        assert_eq!(
+            repository
+                .run(&["diff", "-p", "data.csv", "--from", "42"])
+                .await,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_outside_a_repository() {
+        let repository = Repository::new("outside").await;
+        std::env::set_current_dir(repository.dir.parent().unwrap()).unwrap();
+        assert!(Config::new().unwrap_err().contains("run yap init"));
+        assert_eq!(repository.run(&["status"]).await, 1);
+    }
+}
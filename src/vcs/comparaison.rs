@@ -1,23 +1,59 @@
 use std::{
+    collections::HashMap,
     ffi::OsStr,
     fmt, fs,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use clap::ValueEnum;
-use meowhash::MeowHasher;
+use image::{
+    imageops::FilterType, io::Reader as ImageReader, DynamicImage, GenericImageView,
+};
+use indicatif::ProgressBar;
+use libsql::Builder;
+use polars::prelude::*;
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
+use serde_json::{json, Map, Value};
+use similar::{DiffTag, TextDiff};
+use tokio::process::Command;
 
 use crate::config::Author;
 
-use super::file::{File, FileFacade, LogbookProvider};
+use super::{
+    file::{File, FileFacade, LogbookProvider},
+    objects::{hash_bytes, hash_file},
+};
+
+/// Files compared line by line when using the smart technique
+const TEXT_EXTENSIONS: &[&str] = &[
+    "md", "txt", "rst", "org", "tex", "log", "rs", "py", "r", "jl", "js", "ts", "sh",
+    "sql", "c", "h", "cpp", "go", "java", "rb", "html", "css",
+];
+const UNIFIED_DIFF_MAX_LINES: usize = 200;
+/// Files compared as dataframes when using the smart technique
+const TABULAR_EXTENSIONS: &[&str] = &["csv", "tsv", "parquet"];
+/// Files compared table by table when using the smart technique
+const SQLITE_EXTENSIONS: &[&str] = &["db", "sqlite", "sqlite3"];
+/// Notebooks compared cell by cell when using the smart technique
+const NOTEBOOK_EXTENSIONS: &[&str] = &["ipynb"];
+/// Images compared visually when using the smart technique
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg"];
+/// Rows given as example of the inserted, deleted and updated ones
+const SAMPLE_ROWS: usize = 10;
+/// Files compared key by key when using the smart technique
+const STRUCTURED_EXTENSIONS: &[&str] = &["json", "yaml", "yml", "toml"];
 
 #[derive(ValueEnum, Default, Debug, Clone, Deserialize, PartialEq, Serialize)]
 pub enum ComparaisonTechnique {
     Hash,
     Custom,
     Similarity,
+    Tabular,
+    Structure,
+    Sqlite,
+    Notebook,
+    Image,
     #[default]
     Smart,
 }
@@ -28,6 +64,19 @@ impl fmt::Display for ComparaisonTechnique {
     }
 }
 
+#[derive(Debug, Default, Clone, Deserialize, PartialEq, Serialize)]
+pub enum DiffStatus {
+    #[default]
+    Success,
+    Failed,
+}
+
+impl fmt::Display for DiffStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, PartialEq, Default, Serialize)]
 pub struct Diff {
     pk: Option<u32>,
@@ -35,6 +84,7 @@ pub struct Diff {
     result_path: PathBuf,
     script: Option<PathBuf>,
     result: Value,
+    status: DiffStatus,
     file_from: File,
     file_to: File,
     branch: String,
@@ -52,8 +102,29 @@ impl Diff {
         }
     }
 
-    fn set_result(mut self, result: Value) -> Self {
-        self.result = result;
+    pub fn set_pk(mut self, pk: u32) -> Self {
+        self.pk = Some(pk);
+        self
+    }
+
+    fn set_technique(mut self, technique: &ComparaisonTechnique) -> Self {
+        self.technique = technique.to_owned();
+        self
+    }
+
+    pub fn technique(&self) -> &ComparaisonTechnique {
+        &self.technique
+    }
+
+    pub fn diff_result(&self) -> &Value {
+        &self.result
+    }
+
+    fn set_result(mut self, result: Result<Value, Value>) -> Self {
+        (self.status, self.result) = match result {
+            Ok(v) => (DiffStatus::Success, v),
+            Err(v) => (DiffStatus::Failed, v),
+        };
         self
     }
 
@@ -86,7 +157,7 @@ impl Diff {
 impl LogbookProvider for Diff {
     //TODO: some values are missing add them
     async fn query(&self) -> String {
-        "INSERT INTO diffs (git_commit, result_path, script, result, technique, file_from, file_to, author, branch) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)".to_string()
+        "INSERT INTO diffs (git_commit, result_path, script, result, technique, file_from, file_to, author, branch, status) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)".to_string()
     }
     async fn params(&self) -> Vec<String> {
         vec![
@@ -98,10 +169,11 @@ impl LogbookProvider for Diff {
             self.script(),
             self.result.to_string(),
             self.technique.to_string(),
-            self.file_from.original_path().to_str().unwrap().to_string(),
-            self.file_to.original_path().to_str().unwrap().to_string(),
+            self.file_from.object(),
+            self.file_to.object(),
             self.author.to_string(),
             self.branch.clone(),
+            self.status.to_string(),
         ]
     }
 }
@@ -114,6 +186,15 @@ pub struct Comparaison {
     path: Option<PathBuf>,
     #[serde(default)]
     result: Option<Diff>,
+    // Seconds before killing the custom script
+    #[serde(default)]
+    timeout: u64,
+    // Column identifying the rows of tabular files
+    #[serde(default)]
+    key: Option<String>,
+    // Also summarise the outputs of the notebook cells
+    #[serde(default)]
+    outputs: bool,
 }
 
 impl Comparaison {
@@ -122,75 +203,1239 @@ impl Comparaison {
             technique: technique.to_owned(),
             path: path.to_owned(),
             result: None,
+            timeout: 60,
+            key: None,
+            outputs: false,
         }
     }
 
+    pub fn set_outputs(mut self, outputs: bool) -> Self {
+        self.outputs = outputs;
+        self
+    }
+
+    pub fn set_key(mut self, key: &Option<String>) -> Self {
+        self.key = key.to_owned();
+        self
+    }
+
+    pub fn set_timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
     pub fn result(&self) -> Diff {
         self.result.as_ref().unwrap().clone()
     }
 
-    pub fn compare(&mut self, current: &FileFacade, previous: &FileFacade) -> &Self {
+    pub async fn compare(
+        &mut self,
+        current: &FileFacade,
+        previous: &FileFacade,
+    ) -> &Self {
         let diff_result = match self.technique {
-            ComparaisonTechnique::Hash => self.compare_hash(current, previous),
-            ComparaisonTechnique::Custom => self.compare_custom(
-                current,
-                previous,
-                self.path.as_ref().expect(
-                    "You must provide a script if you select the custom comparaison technique",
-                ),
-            ),
-            ComparaisonTechnique::Similarity => self.compare_similarity(current, previous),
-            ComparaisonTechnique::Smart => self.compare_smart(current, previous),
+            ComparaisonTechnique::Hash => Ok(self.compare_hash(current, previous).await),
+            ComparaisonTechnique::Custom => match &self.path {
+                Some(script) => self.compare_custom(current, previous, script).await,
+                None => Err(json!({
+                    "error": "You must provide a script if you select the custom comparaison technique"
+                })),
+            },
+            ComparaisonTechnique::Similarity => {
+                self.compare_similarity(current, previous)
+            },
+            ComparaisonTechnique::Tabular => self.compare_tabular(current, previous),
+            ComparaisonTechnique::Structure => self.compare_structure(current, previous),
+            ComparaisonTechnique::Sqlite => self.compare_sqlite(current, previous).await,
+            ComparaisonTechnique::Notebook => self.compare_notebook(current, previous),
+            ComparaisonTechnique::Image => self.compare_image(current, previous),
+            ComparaisonTechnique::Smart => self.compare_smart(current, previous).await,
         };
         let result = Diff::new(self.path.clone(), previous.file(), current.file())
+            .set_technique(&self.technique)
             .set_result(diff_result);
         self.result = Some(result);
         self
     }
 
-    pub fn compare_smart(&self, current: &FileFacade, previous: &FileFacade) -> Value {
-        let mut first = self.compare_hash(current, previous);
-        let second = match current
+    pub async fn compare_smart(
+        &self,
+        current: &FileFacade,
+        previous: &FileFacade,
+    ) -> Result<Value, Value> {
+        let mut first = self.compare_hash(current, previous).await;
+        let extension = current
             .original_path()
             .extension()
             .and_then(OsStr::to_str)
-            .unwrap()
-        {
-            "md" => self.compare_similarity(current, previous),
-            "parquet" => self.compare_similarity(current, previous),
-            _ => first.clone(),
+            .unwrap_or_default()
+            .to_lowercase();
+        let second = match extension.as_str() {
+            e if TEXT_EXTENSIONS.contains(&e) => {
+                self.compare_similarity(current, previous)
+            },
+            e if TABULAR_EXTENSIONS.contains(&e) => {
+                self.compare_tabular(current, previous)
+            },
+            e if STRUCTURED_EXTENSIONS.contains(&e) => {
+                self.compare_structure(current, previous)
+            },
+            e if NOTEBOOK_EXTENSIONS.contains(&e) => {
+                self.compare_notebook(current, previous)
+            },
+            e if IMAGE_EXTENSIONS.contains(&e) => self.compare_image(current, previous),
+            e if SQLITE_EXTENSIONS.contains(&e) => {
+                self.compare_sqlite(current, previous).await
+            },
+            _ => Ok(first.clone()),
         };
+        // A file the extension lies about, like a binary .txt, still gets the hash comparaison
+        let second = second.unwrap_or_else(|_| first.clone());
         Diff::merge_results(&mut first, &second);
-        first
+        Ok(first)
     }
 
+    /// Line based diff of text files, with a word based ratio to tell apart small edits
     pub fn compare_similarity(
         &self,
         current: &FileFacade,
         previous: &FileFacade,
-    ) -> Value {
-        //TODO: rm this awesome trick
-        self.compare_hash(current, previous)
+    ) -> Result<Value, Value> {
+        let (previous_text, current_text) = match (
+            fs::read_to_string(previous.content_path()),
+            fs::read_to_string(current.content_path()),
+        ) {
+            (Ok(p), Ok(c)) => (p, c),
+            _ => return Err(json!({"error": "not a utf-8 text file"})),
+        };
+        let diff = TextDiff::from_lines(&previous_text, &current_text);
+        let (mut added, mut removed, mut changed) = (0, 0, 0);
+        for op in diff.ops() {
+            let (old, new) = (op.old_range().len(), op.new_range().len());
+            match op.tag() {
+                DiffTag::Insert => added += new,
+                DiffTag::Delete => removed += old,
+                DiffTag::Replace => {
+                    changed += old.min(new);
+                    added += new.saturating_sub(old);
+                    removed += old.saturating_sub(new);
+                },
+                DiffTag::Equal => (),
+            }
+        }
+        let unified = diff
+            .unified_diff()
+            .context_radius(3)
+            .header("previous", "current")
+            .to_string();
+        let lines = unified.lines().count();
+        let excerpt = unified
+            .lines()
+            .take(UNIFIED_DIFF_MAX_LINES)
+            .collect::<Vec<&str>>()
+            .join("\n");
+        Ok(json!({"similarity": {
+            "lines_added": added,
+            "lines_removed": removed,
+            "lines_changed": changed,
+            "ratio": diff.ratio(),
+            "words_ratio": TextDiff::from_words(&previous_text, &current_text).ratio(),
+            "unified_diff": excerpt,
+            "truncated": lines > UNIFIED_DIFF_MAX_LINES,
+        }}))
     }
-    //TODO: create the functions to compare parquets files with polars, the function to compare text
-    //only files and the one to call the custom script
-    pub fn compare_custom(
+
+    /// Compare csv and parquet files as dataframes: schema, row count, summary statistics of
+    /// the numeric columns and a sample of the rows that changed
+    pub fn compare_tabular(
         &self,
-        _current: &FileFacade,
-        _previous: &FileFacade,
-        _script: &Path,
+        current: &FileFacade,
+        previous: &FileFacade,
+    ) -> Result<Value, Value> {
+        let (previous_df, current_df) = match (
+            read_dataframe(&previous.content_path(), &previous.original_path()),
+            read_dataframe(&current.content_path(), &current.original_path()),
+        ) {
+            (Ok(p), Ok(c)) => (p, c),
+            (Err(err), _) | (_, Err(err)) => {
+                return Err(json!({"error": err.to_string()}))
+            },
+        };
+        let columns = |df: &DataFrame| -> Vec<(String, String)> {
+            df.get_columns()
+                .iter()
+                .map(|c| (c.name().to_string(), c.dtype().to_string()))
+                .collect()
+        };
+        let (previous_columns, current_columns) =
+            (columns(&previous_df), columns(&current_df));
+        let previous_types: HashMap<&String, &String> =
+            previous_columns.iter().map(|(n, t)| (n, t)).collect();
+        let current_types: HashMap<&String, &String> =
+            current_columns.iter().map(|(n, t)| (n, t)).collect();
+
+        let added: Vec<&String> = current_columns
+            .iter()
+            .map(|(n, _)| n)
+            .filter(|n| !previous_types.contains_key(n))
+            .collect();
+        let removed: Vec<&String> = previous_columns
+            .iter()
+            .map(|(n, _)| n)
+            .filter(|n| !current_types.contains_key(n))
+            .collect();
+        let retyped: Map<String, Value> = current_columns
+            .iter()
+            .filter_map(|(name, dtype)| match previous_types.get(name) {
+                Some(old) if *old != dtype => {
+                    Some((name.clone(), json!({"old": old, "new": dtype})))
+                },
+                _ => None,
+            })
+            .collect();
+        let common: Vec<&str> = current_columns
+            .iter()
+            .map(|(n, _)| n.as_str())
+            .filter(|n| previous_types.contains_key(&n.to_string()))
+            .collect();
+        let changes = changed_rows(&previous_df, &current_df, &common, &self.key)?;
+
+        Ok(json!({"tabular": {
+            "schema": {"added": added, "removed": removed, "retyped": retyped},
+            "rows": {
+                "previous": previous_df.height(),
+                "current": current_df.height(),
+                "delta": current_df.height() as i64 - previous_df.height() as i64,
+            },
+            "statistics": column_statistics(&previous_df, &current_df, &common),
+            "changes": changes,
+        }}))
+    }
+
+    /// Parse json, yaml and toml files and list the keys added, removed and the values
+    /// changed by their path, e.g. `server.ports[1]`
+    pub fn compare_structure(
+        &self,
+        current: &FileFacade,
+        previous: &FileFacade,
+    ) -> Result<Value, Value> {
+        let (previous_value, current_value) = match (
+            read_structure(&previous.content_path(), &previous.original_path()),
+            read_structure(&current.content_path(), &current.original_path()),
+        ) {
+            (Ok(p), Ok(c)) => (p, c),
+            (Err(err), _) | (_, Err(err)) => return Err(json!({"error": err})),
+        };
+        let mut result =
+            json!({"structure": {"added": {}, "removed": {}, "changed": {}}});
+        structure_diff("", &previous_value, &current_value, &mut result);
+        Ok(result)
+    }
+
+    /// Compare png and jpeg images: dimensions, distance between their perceptual hashes and
+    /// the share of pixels that differ. A version with other dimensions is resized first.
+    pub fn compare_image(
+        &self,
+        current: &FileFacade,
+        previous: &FileFacade,
+    ) -> Result<Value, Value> {
+        let (previous_image, current_image) = match (
+            read_image(&previous.content_path()),
+            read_image(&current.content_path()),
+        ) {
+            (Ok(p), Ok(c)) => (p, c),
+            (Err(err), _) | (_, Err(err)) => return Err(json!({"error": err})),
+        };
+        let (width, height) = previous_image.dimensions();
+        let resized = current_image.dimensions() != (width, height);
+        let old = previous_image.to_rgba8();
+        let new = match resized {
+            true => current_image
+                .resize_exact(width, height, FilterType::Triangle)
+                .to_rgba8(),
+            false => current_image.to_rgba8(),
+        };
+        let changed = old
+            .pixels()
+            .zip(new.pixels())
+            .filter(|(o, n)| o != n)
+            .count();
+        let pixels = (width as u64 * height as u64).max(1);
+
+        Ok(json!({"image": {
+            "dimensions": {
+                "previous": {"width": width, "height": height},
+                "current": {
+                    "width": current_image.width(),
+                    "height": current_image.height(),
+                },
+            },
+            "resized": resized,
+            "dhash_distance": (dhash(&previous_image) ^ dhash(&current_image)).count_ones(),
+            "pixel_diff": changed as f64 / pixels as f64 * 100.0,
+        }}))
+    }
+
+    /// Compare the sources and the order of the notebook cells. Execution counts and metadata
+    /// are ignored, outputs are only summarised when asked.
+    pub fn compare_notebook(
+        &self,
+        current: &FileFacade,
+        previous: &FileFacade,
+    ) -> Result<Value, Value> {
+        let (previous_cells, current_cells) = match (
+            read_cells(&previous.content_path()),
+            read_cells(&current.content_path()),
+        ) {
+            (Ok(p), Ok(c)) => (p, c),
+            (Err(err), _) | (_, Err(err)) => return Err(json!({"error": err})),
+        };
+        let previous_sources: Vec<String> =
+            previous_cells.iter().map(cell_source).collect();
+        let current_sources: Vec<String> =
+            current_cells.iter().map(cell_source).collect();
+
+        // Pairs of (previous index, current index) for the cells kept, moved or edited
+        let mut paired = Vec::new();
+        let (mut added, mut removed) = (Vec::new(), Vec::new());
+        for op in similar::capture_diff_slices(
+            similar::Algorithm::Myers,
+            &previous_sources,
+            &current_sources,
+        ) {
+            let (tag, old, new) = op.as_tag_tuple();
+            match tag {
+                DiffTag::Equal => paired.extend(old.zip(new)),
+                DiffTag::Delete => removed.extend(old),
+                DiffTag::Insert => added.extend(new),
+                DiffTag::Replace => {
+                    removed.extend(old);
+                    added.extend(new);
+                },
+            }
+        }
+        // A cell removed at one place and added at another with the same source was only
+        // moved, with a close enough source it was edited
+        let (mut moved, mut edited) = (Vec::new(), Vec::new());
+        added.retain(|n| {
+            let current_source = &current_sources[*n];
+            if let Some(position) = removed
+                .iter()
+                .position(|o| &previous_sources[*o] == current_source)
+            {
+                let o = removed.remove(position);
+                paired.push((o, *n));
+                moved.push(json!({"previous": o, "current": n}));
+                return false;
+            }
+            let closest = removed
+                .iter()
+                .enumerate()
+                .map(|(position, o)| {
+                    let ratio =
+                        TextDiff::from_chars(&previous_sources[*o], current_source)
+                            .ratio();
+                    (position, ratio)
+                })
+                .filter(|(_, ratio)| *ratio >= 0.5)
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            match closest {
+                Some((position, _)) => {
+                    let o = removed.remove(position);
+                    paired.push((o, *n));
+                    edited.push(json!({
+                        "previous": o,
+                        "current": n,
+                        "diff": TextDiff::from_lines(&previous_sources[o], current_source)
+                            .unified_diff()
+                            .to_string(),
+                    }));
+                    false
+                },
+                None => true,
+            }
+        });
+
+        let mut result = json!({"notebook": {
+            "cells": {"previous": previous_cells.len(), "current": current_cells.len()},
+            "added": added,
+            "removed": removed,
+            "edited": edited,
+            "moved": moved,
+        }});
+        if self.outputs {
+            let changed: Vec<Value> = paired
+                .iter()
+                .filter(|(o, n)| {
+                    cell_outputs(&previous_cells[*o]) != cell_outputs(&current_cells[*n])
+                })
+                .map(|(o, n)| {
+                    json!({
+                        "previous": o,
+                        "current": n,
+                        "outputs": {
+                            "previous": cell_outputs(&previous_cells[*o]).len(),
+                            "current": cell_outputs(&current_cells[*n]).len(),
+                        },
+                    })
+                })
+                .collect();
+            Diff::merge_results(&mut result, &json!({"notebook": {"outputs": changed}}));
+        }
+        Ok(result)
+    }
+
+    /// Open both versions as sqlite databases and compare them table by table: schema, row
+    /// count and the rows inserted, deleted or updated by primary key
+    pub async fn compare_sqlite(
+        &self,
+        current: &FileFacade,
+        previous: &FileFacade,
+    ) -> Result<Value, Value> {
+        let (previous_tables, current_tables) = match (
+            read_database(&previous.content_path(), "previous").await,
+            read_database(&current.content_path(), "current").await,
+        ) {
+            (Ok(p), Ok(c)) => (p, c),
+            (Err(err), _) | (_, Err(err)) => return Err(json!({"error": err})),
+        };
+        let added: Vec<&String> = current_tables
+            .keys()
+            .filter(|t| !previous_tables.contains_key(*t))
+            .collect();
+        let removed: Vec<&String> = previous_tables
+            .keys()
+            .filter(|t| !current_tables.contains_key(*t))
+            .collect();
+        let tables: Map<String, Value> = current_tables
+            .iter()
+            .filter_map(|(name, new)| {
+                let old = previous_tables.get(name)?;
+                Some((name.clone(), table_diff(old, new)))
+            })
+            .collect();
+        Ok(json!({"sqlite": {
+            "tables": {"added": added, "removed": removed},
+            "changes": tables,
+        }}))
+    }
+
+    /// Run the script with the previous and the current version paths. Its stdout must be a
+    /// json that becomes the result. Any failure is returned as the error json so it can be
+    /// saved with the diff.
+    pub async fn compare_custom(
+        &self,
+        current: &FileFacade,
+        previous: &FileFacade,
+        script: &Path,
+    ) -> Result<Value, Value> {
+        let script = fs::canonicalize(script).unwrap_or(script.to_path_buf());
+        let mut command = Command::new(&script);
+        command
+            .arg(previous.content_path())
+            .arg(current.content_path())
+            .kill_on_drop(true);
+        let output = command.output();
+        let output = match tokio::time::timeout(Duration::from_secs(self.timeout), output)
+            .await
+        {
+            Ok(Ok(output)) => output,
+            Ok(Err(err)) => {
+                return Err(
+                    json!({"error": format!("unable to run {:?}: {err}", script)}),
+                )
+            },
+            Err(_) => {
+                return Err(
+                    json!({"error": format!("{:?} timed out after {}s", script, self.timeout)}),
+                )
+            },
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() {
+            return Err(json!({
+                "error": format!("{:?} failed", script),
+                "code": output.status.code(),
+                "stdout": stdout,
+                "stderr": String::from_utf8_lossy(&output.stderr),
+            }));
+        }
+        serde_json::from_str(&stdout).map_err(|err| {
+            json!({
+                "error": format!("{:?} didn't print a valid json: {err}", script),
+                "stdout": stdout,
+            })
+        })
+    }
+
+    pub async fn compare_hash(
+        &self,
+        current: &FileFacade,
+        previous: &FileFacade,
     ) -> Value {
-        todo!()
-    }
-    pub fn compare_hash(&self, current: &FileFacade, previous: &FileFacade) -> Value {
-        let current_file =
-            fs::read(current.original_path()).expect("cannot open orinigal file");
-        let current = MeowHasher::hash(&current_file);
-        //TODO: better handl errors
-        let previous_file =
-            fs::read(previous.original_path()).expect("cannot open previous copy");
-        let previous = MeowHasher::hash(&previous_file);
-        let _ = current.eq(&previous);
-        json!({"":""})
+        let current = Self::object_of(current).await;
+        let previous = Self::object_of(previous).await;
+        json!({"hash": {
+            "previous": previous,
+            "current": current,
+            "equal": current == previous,
+        }})
+    }
+
+    /// Versions already in the history are named by their hash so no need to read them
+    async fn object_of(version: &FileFacade) -> String {
+        match version.file().object() {
+            o if !o.is_empty() => o,
+            _ => hash_file(&version.content_path(), &ProgressBar::hidden()).await,
+        }
+    }
+}
+
+/// Objects have no extension so the format is guessed from the content
+fn read_image(path: &Path) -> Result<DynamicImage, String> {
+    ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|err| err.to_string())?
+        .decode()
+        .map_err(|err| err.to_string())
+}
+
+/// Difference hash: each bit tells if a pixel is brighter than its right neighbour in a
+/// 9x8 grayscale thumbnail
+fn dhash(image: &DynamicImage) -> u64 {
+    let thumbnail = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if thumbnail.get_pixel(x, y)[0] > thumbnail.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+fn read_cells(path: &Path) -> Result<Vec<Value>, String> {
+    let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let notebook = serde_json::from_str::<Value>(&text).map_err(|err| err.to_string())?;
+    match notebook.get("cells") {
+        Some(Value::Array(cells)) => Ok(cells.clone()),
+        _ => Err("the notebook has no cells".to_string()),
+    }
+}
+
+/// Sources are either a string or a list of lines
+fn cell_source(cell: &Value) -> String {
+    let source = match &cell["source"] {
+        Value::Array(lines) => lines.iter().filter_map(Value::as_str).collect(),
+        Value::String(source) => source.clone(),
+        _ => String::new(),
+    };
+    format!(
+        "[{}]\n{}",
+        cell["cell_type"].as_str().unwrap_or_default(),
+        source
+    )
+}
+
+/// Outputs without their execution count, which changes on every run
+fn cell_outputs(cell: &Value) -> Vec<Value> {
+    cell["outputs"]
+        .as_array()
+        .map(|outputs| {
+            outputs
+                .iter()
+                .map(|output| {
+                    let mut output = output.clone();
+                    if let Some(o) = output.as_object_mut() {
+                        o.remove("execution_count");
+                    }
+                    output
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Columns as (name, type) and rows indexed by their primary key, or rowid without one
+#[derive(Debug, Default)]
+struct Table {
+    columns: Vec<(String, String)>,
+    rows: HashMap<String, Vec<String>>,
+}
+
+fn sqlite_value(value: libsql::Value) -> String {
+    match value {
+        libsql::Value::Null => "NULL".to_string(),
+        libsql::Value::Integer(i) => i.to_string(),
+        libsql::Value::Real(r) => r.to_string(),
+        libsql::Value::Text(t) => t,
+        libsql::Value::Blob(b) => format!("blob:{}", hash_bytes(&b)),
+    }
+}
+
+/// Load every table of the database. It is opened from a temporary copy so sqlite never
+/// writes journal files inside the history.
+async fn read_database(
+    path: &Path,
+    label: &str,
+) -> Result<HashMap<String, Table>, String> {
+    let id = hash_bytes(path.to_string_lossy().as_bytes());
+    let copy = std::env::temp_dir().join(format!("yap-{label}-{id}.db"));
+    fs::copy(path, &copy).map_err(|err| err.to_string())?;
+    let tables = read_tables(&copy).await.map_err(|err| err.to_string());
+    let _ = fs::remove_file(&copy);
+    tables
+}
+
+async fn read_tables(path: &Path) -> Result<HashMap<String, Table>, libsql::Error> {
+    let db = Builder::new_local(path).build().await?;
+    let conn = db.connect()?;
+    let mut names = Vec::new();
+    let mut rows = conn
+        .query(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+            (),
+        )
+        .await?;
+    while let Some(row) = rows.next().await? {
+        names.push(row.get::<String>(0)?);
+    }
+
+    let mut tables = HashMap::new();
+    for name in names {
+        let mut table = Table::default();
+        let mut keys = Vec::new();
+        let mut info = conn
+            .query(&format!("PRAGMA table_info(\"{name}\")"), ())
+            .await?;
+        while let Some(row) = info.next().await? {
+            let column = row.get::<String>(1)?;
+            if row.get::<i64>(5)? > 0 {
+                keys.push(table.columns.len());
+            }
+            table.columns.push((column, row.get::<String>(2)?));
+        }
+        // Tables without primary key are matched on their rowid, selected as first column
+        let (query, offset) = match keys.is_empty() {
+            true => (format!("SELECT rowid, * FROM \"{name}\""), 1),
+            false => (format!("SELECT * FROM \"{name}\""), 0),
+        };
+        let mut content = conn.query(&query, ()).await?;
+        while let Some(row) = content.next().await? {
+            let mut values = Vec::new();
+            for i in 0..table.columns.len() + offset {
+                values.push(sqlite_value(row.get_value(i as i32)?));
+            }
+            let key = match keys.is_empty() {
+                true => values.remove(0),
+                false => keys
+                    .iter()
+                    .map(|k| values[*k].as_str())
+                    .collect::<Vec<&str>>()
+                    .join("|"),
+            };
+            table.rows.insert(key, values);
+        }
+        tables.insert(name, table);
+    }
+    Ok(tables)
+}
+
+fn table_diff(previous: &Table, current: &Table) -> Value {
+    let previous_types: HashMap<&String, &String> =
+        previous.columns.iter().map(|(n, t)| (n, t)).collect();
+    let current_types: HashMap<&String, &String> =
+        current.columns.iter().map(|(n, t)| (n, t)).collect();
+    let added: Vec<&String> = current
+        .columns
+        .iter()
+        .map(|(n, _)| n)
+        .filter(|n| !previous_types.contains_key(n))
+        .collect();
+    let removed: Vec<&String> = previous
+        .columns
+        .iter()
+        .map(|(n, _)| n)
+        .filter(|n| !current_types.contains_key(n))
+        .collect();
+    let retyped: Map<String, Value> = current
+        .columns
+        .iter()
+        .filter_map(|(name, dtype)| match previous_types.get(name) {
+            Some(old) if *old != dtype => {
+                Some((name.clone(), json!({"old": old, "new": dtype})))
+            },
+            _ => None,
+        })
+        .collect();
+
+    // Values are compared on the columns present in both versions
+    let value = |table: &Table, row: &[String], column: &String| -> Option<String> {
+        let index = table.columns.iter().position(|(n, _)| n == column)?;
+        row.get(index).cloned()
+    };
+    let common: Vec<&String> = current
+        .columns
+        .iter()
+        .map(|(n, _)| n)
+        .filter(|n| previous_types.contains_key(n))
+        .collect();
+    let inserted: Vec<&String> = current
+        .rows
+        .keys()
+        .filter(|k| !previous.rows.contains_key(*k))
+        .collect();
+    let deleted: Vec<&String> = previous
+        .rows
+        .keys()
+        .filter(|k| !current.rows.contains_key(*k))
+        .collect();
+    let updated: Vec<Value> = current
+        .rows
+        .iter()
+        .filter_map(|(key, new)| {
+            let old = previous.rows.get(key)?;
+            let changes: Map<String, Value> = common
+                .iter()
+                .filter_map(|c| {
+                    let (o, n) = (value(previous, old, c), value(current, new, c));
+                    (o != n).then(|| (c.to_string(), json!({"old": o, "new": n})))
+                })
+                .collect();
+            (!changes.is_empty()).then(|| json!({"key": key, "columns": changes}))
+        })
+        .collect();
+    json!({
+        "schema": {"added": added, "removed": removed, "retyped": retyped},
+        "rows": {
+            "previous": previous.rows.len(),
+            "current": current.rows.len(),
+            "delta": current.rows.len() as i64 - previous.rows.len() as i64,
+        },
+        "inserted": inserted.len(),
+        "deleted": deleted.len(),
+        "updated": updated.len(),
+        "sample": {
+            "inserted": inserted.iter().take(SAMPLE_ROWS).collect::<Vec<_>>(),
+            "deleted": deleted.iter().take(SAMPLE_ROWS).collect::<Vec<_>>(),
+            "updated": updated.iter().take(SAMPLE_ROWS).collect::<Vec<_>>(),
+        },
+    })
+}
+
+/// The content may be an object without extension so the original path tells the format
+fn read_structure(content: &Path, original: &Path) -> Result<Value, String> {
+    let text = fs::read_to_string(content).map_err(|err| err.to_string())?;
+    match original.extension().and_then(OsStr::to_str) {
+        Some("yaml") | Some("yml") => {
+            serde_yaml::from_str::<Value>(&text).map_err(|err| err.to_string())
+        },
+        Some("toml") => toml::from_str::<Value>(&text).map_err(|err| err.to_string()),
+        _ => serde_json::from_str::<Value>(&text).map_err(|err| err.to_string()),
+    }
+}
+
+fn child_path(parent: &str, key: &str) -> String {
+    if parent.is_empty() {
+        key.to_string()
+    } else {
+        format!("{parent}.{key}")
+    }
+}
+
+/// Walk both values and merge every difference found into the result under its path
+fn structure_diff(path: &str, previous: &Value, current: &Value, result: &mut Value) {
+    match (previous, current) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, value) in old {
+                let child = child_path(path, key);
+                match new.get(key) {
+                    Some(other) => structure_diff(&child, value, other, result),
+                    None => Diff::merge_results(
+                        result,
+                        &json!({"structure": {"removed": {child: value}}}),
+                    ),
+                }
+            }
+            for (key, value) in new.iter().filter(|(k, _)| !old.contains_key(*k)) {
+                Diff::merge_results(
+                    result,
+                    &json!({"structure": {"added": {child_path(path, key): value}}}),
+                );
+            }
+        },
+        (Value::Array(old), Value::Array(new)) => {
+            for (i, value) in old.iter().enumerate() {
+                let child = format!("{path}[{i}]");
+                match new.get(i) {
+                    Some(other) => structure_diff(&child, value, other, result),
+                    None => Diff::merge_results(
+                        result,
+                        &json!({"structure": {"removed": {child: value}}}),
+                    ),
+                }
+            }
+            for (i, value) in new.iter().enumerate().skip(old.len()) {
+                Diff::merge_results(
+                    result,
+                    &json!({"structure": {"added": {format!("{path}[{i}]"): value}}}),
+                );
+            }
+        },
+        (old, new) if old != new => Diff::merge_results(
+            result,
+            &json!({"structure": {"changed": {path: {"old": old, "new": new}}}}),
+        ),
+        _ => {},
+    }
+}
+
+/// The content may be an object without extension so the original path tells the format
+fn read_dataframe(content: &Path, original: &Path) -> PolarsResult<DataFrame> {
+    match original.extension().and_then(OsStr::to_str) {
+        Some("parquet") => ParquetReader::new(fs::File::open(content)?).finish(),
+        Some("tsv") => CsvReader::from_path(content)?
+            .has_header(true)
+            .with_separator(b'\t')
+            .finish(),
+        _ => CsvReader::from_path(content)?.has_header(true).finish(),
+    }
+}
+
+fn column_summary(series: &Series) -> Value {
+    let values = series
+        .cast(&DataType::Float64)
+        .ok()
+        .and_then(|s| s.f64().ok().map(|v| (v.mean(), v.min(), v.max())));
+    match values {
+        Some((mean, min, max)) => json!({
+            "mean": mean,
+            "min": min,
+            "max": max,
+            "nulls": series.null_count(),
+        }),
+        None => json!({"nulls": series.null_count()}),
+    }
+}
+
+/// Summary of the numeric columns present in both versions and how much they moved
+fn column_statistics(
+    previous: &DataFrame,
+    current: &DataFrame,
+    common: &[&str],
+) -> Value {
+    let mut statistics = Map::new();
+    for name in common {
+        let (old, new) = match (previous.column(name), current.column(name)) {
+            (Ok(o), Ok(n)) if o.dtype().is_numeric() && n.dtype().is_numeric() => (o, n),
+            _ => continue,
+        };
+        let (old, new) = (column_summary(old), column_summary(new));
+        let delta = |field: &str| match (old[field].as_f64(), new[field].as_f64()) {
+            (Some(o), Some(n)) => json!(n - o),
+            _ => Value::Null,
+        };
+        statistics.insert(
+            name.to_string(),
+            json!({
+                "previous": old,
+                "current": new,
+                "delta": {"mean": delta("mean"), "min": delta("min"), "max": delta("max")},
+            }),
+        );
+    }
+    Value::Object(statistics)
+}
+
+fn row_values(columns: &[&Series], index: usize) -> Vec<String> {
+    columns
+        .iter()
+        .map(|c| c.get(index).map(|v| v.to_string()).unwrap_or_default())
+        .collect()
+}
+
+/// Rows indexed by the key column, or by their values when there is no key so an insertion
+/// doesn't shift every row after it. Duplicated rows are numbered to be counted each.
+fn indexed_rows(
+    df: &DataFrame,
+    common: &[&str],
+    key: &Option<String>,
+) -> PolarsResult<Vec<(String, Vec<String>)>> {
+    let columns = common
+        .iter()
+        .map(|c| df.column(c))
+        .collect::<PolarsResult<Vec<&Series>>>()?;
+    let key_column = match key {
+        Some(k) => Some(df.column(k)?),
+        None => None,
+    };
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    Ok((0..df.height())
+        .map(|i| {
+            let values = row_values(&columns, i);
+            let index = match key_column {
+                Some(k) => k.get(i).map(|v| v.to_string()).unwrap_or_default(),
+                None => {
+                    let row = values.join(",");
+                    let count = seen.entry(row.clone()).or_default();
+                    *count += 1;
+                    match count {
+                        1 => row,
+                        n => format!("{row} ({n})"),
+                    }
+                },
+            };
+            (index, values)
+        })
+        .collect())
+}
+
+/// Inserted, deleted and updated rows with a sample of each
+fn changed_rows(
+    previous: &DataFrame,
+    current: &DataFrame,
+    common: &[&str],
+    key: &Option<String>,
+) -> Result<Value, Value> {
+    let (old_rows, new_rows) = match (
+        indexed_rows(previous, common, key),
+        indexed_rows(current, common, key),
+    ) {
+        (Ok(o), Ok(n)) => (o, n),
+        (Err(err), _) | (_, Err(err)) => return Err(json!({"error": err.to_string()})),
+    };
+    let old_index: HashMap<&String, &Vec<String>> =
+        old_rows.iter().map(|(k, v)| (k, v)).collect();
+    let new_index: HashMap<&String, &Vec<String>> =
+        new_rows.iter().map(|(k, v)| (k, v)).collect();
+
+    let inserted: Vec<&String> = new_rows
+        .iter()
+        .map(|(k, _)| k)
+        .filter(|k| !old_index.contains_key(k))
+        .collect();
+    let deleted: Vec<&String> = old_rows
+        .iter()
+        .map(|(k, _)| k)
+        .filter(|k| !new_index.contains_key(k))
+        .collect();
+    let updated: Vec<Value> = new_rows
+        .iter()
+        .filter_map(|(k, new)| {
+            let old = old_index.get(k)?;
+            let changes: Map<String, Value> = common
+                .iter()
+                .zip(old.iter().zip(new.iter()))
+                .filter(|(_, (o, n))| o != n)
+                .map(|(c, (o, n))| (c.to_string(), json!({"old": o, "new": n})))
+                .collect();
+            (!changes.is_empty()).then(|| json!({"key": k, "columns": changes}))
+        })
+        .collect();
+    Ok(json!({
+        "key": key,
+        "inserted": inserted.len(),
+        "deleted": deleted.len(),
+        "updated": updated.len(),
+        "sample": {
+            "inserted": inserted.iter().take(SAMPLE_ROWS).collect::<Vec<_>>(),
+            "deleted": deleted.iter().take(SAMPLE_ROWS).collect::<Vec<_>>(),
+            "updated": updated.iter().take(SAMPLE_ROWS).collect::<Vec<_>>(),
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn test_compare_similarity() {
+        let previous = facade(
+            "yap-similarity-previous.md",
+            "# Title\nfirst\nsecond\nthird\n",
+        );
+        let current = facade(
+            "yap-similarity-current.md",
+            "# Title\nfirst\nsecond edited\nthird\nfourth\n",
+        );
+        let comparaison = Comparaison::new(&ComparaisonTechnique::Similarity, &None);
+        let result =
+            &comparaison.compare_similarity(&current, &previous).unwrap()["similarity"];
+        assert_eq!(result["lines_added"], 1);
+        assert_eq!(result["lines_removed"], 0);
+        assert_eq!(result["lines_changed"], 1);
+        assert!(result["ratio"].as_f64().unwrap() < 1.0);
+        assert!(result["unified_diff"]
+            .as_str()
+            .unwrap()
+            .contains("+second edited"));
+
+        let result = comparaison
+            .compare_smart(&current, &previous)
+            .await
+            .unwrap();
+        assert_eq!(result["hash"]["equal"], false);
+        assert!(result.get("similarity").is_some());
+
+        let binary = facade("yap-similarity-binary.md", [0xff, 0xfe, 0x00, 0xc3]);
+        assert!(comparaison.compare_similarity(&binary, &previous).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_compare_smart_falls_back_to_hash() {
+        let previous = facade("yap-smart-previous.txt", "some text\n");
+        let current = facade("yap-smart-binary.txt", [0xff, 0xfe, 0x00, 0xc3]);
+        let result = Comparaison::new(&ComparaisonTechnique::Smart, &None)
+            .compare_smart(&current, &previous)
+            .await
+            .unwrap();
+        assert_eq!(result["hash"]["equal"], false);
+        assert!(result.get("similarity").is_none());
+    }
+
+    #[test]
+    fn test_compare_structure() {
+        let previous = facade(
+            "yap-structure-previous.yaml",
+            "name: yap\nserver:\n  port: 80\n  hosts: [a, b]\ndebug: true\n",
+        );
+        let current = facade(
+            "yap-structure-current.yaml",
+            "name: yap\nserver:\n  port: 8080\n  hosts: [a]\nlevel: 2\n",
+        );
+        let comparaison = Comparaison::new(&ComparaisonTechnique::Structure, &None);
+        let result = comparaison.compare_structure(&current, &previous).unwrap();
+        let result = &result["structure"];
+        assert_eq!(result["added"], json!({"level": 2}));
+        assert_eq!(
+            result["removed"],
+            json!({"debug": true, "server.hosts[1]": "b"})
+        );
+        assert_eq!(
+            result["changed"],
+            json!({"server.port": {"old": 80, "new": 8080}})
+        );
+
+        let broken = facade("yap-structure-broken.yaml", "server: [unclosed\n");
+        assert!(comparaison.compare_structure(&broken, &previous).is_err());
+    }
+
+    async fn database(name: &str, statements: &str) -> FileFacade {
+        let path = temp_path(name);
+        let _ = fs::remove_file(&path);
+        let db = Builder::new_local(&path).build().await.unwrap();
+        db.connect()
+            .unwrap()
+            .execute_batch(statements)
+            .await
+            .unwrap();
+        FileFacade::new(File::new(&path, "master", "", 0, Author::default()))
+    }
+
+    #[tokio::test]
+    async fn test_compare_sqlite() {
+        let previous = database(
+            "yap-sqlite-previous.db",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+            INSERT INTO users VALUES (1, 'ada'), (2, 'bob'), (3, 'cid');
+            CREATE TABLE logs (message TEXT);",
+        )
+        .await;
+        let current = database(
+            "yap-sqlite-current.db",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, age INTEGER);
+            INSERT INTO users VALUES (1, 'ada', 36), (2, 'bobby', 20), (4, 'dan', 5);",
+        )
+        .await;
+        let comparaison = Comparaison::new(&ComparaisonTechnique::Sqlite, &None);
+        let result = comparaison
+            .compare_sqlite(&current, &previous)
+            .await
+            .unwrap();
+        let result = &result["sqlite"];
+        assert_eq!(result["tables"]["removed"], json!(["logs"]));
+        let users = &result["changes"]["users"];
+        assert_eq!(users["schema"]["added"], json!(["age"]));
+        assert_eq!(users["inserted"], 1);
+        assert_eq!(users["deleted"], 1);
+        assert_eq!(users["updated"], 1);
+        assert_eq!(users["sample"]["updated"][0]["key"], "2");
+
+        let broken = facade("yap-sqlite-broken.db", "not a database");
+        assert!(comparaison
+            .compare_sqlite(&broken, &previous)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_compare_image() {
+        let image = |name: &str, size: u32, edited: bool| -> FileFacade {
+            let path = temp_path(name);
+            image::RgbImage::from_fn(size, size, |x, y| match edited && x < size / 4 {
+                true => image::Rgb([255, 0, 0]),
+                false => image::Rgb([(x * 255 / size) as u8, (y * 255 / size) as u8, 0]),
+            })
+            .save(&path)
+            .unwrap();
+            FileFacade::new(File::new(&path, "master", "", 0, Author::default()))
+        };
+        let previous = image("yap-image-previous.png", 64, false);
+        let comparaison = Comparaison::new(&ComparaisonTechnique::Image, &None);
+
+        let result = comparaison.compare_image(&previous, &previous).unwrap();
+        assert_eq!(result["image"]["dhash_distance"], 0);
+        assert_eq!(result["image"]["pixel_diff"], 0.0);
+
+        let current = image("yap-image-current.png", 32, true);
+        let result = &comparaison.compare_image(&current, &previous).unwrap()["image"];
+        assert_eq!(result["resized"], true);
+        assert_eq!(result["dimensions"]["current"]["width"], 32);
+        assert!(result["pixel_diff"].as_f64().unwrap() >= 25.0);
+
+        let broken = facade("yap-image-broken.png", "not an image");
+        assert!(comparaison.compare_image(&broken, &previous).is_err());
+    }
+
+    #[test]
+    fn test_compare_notebook() {
+        let notebook = |cells: Value| json!({"cells": cells, "metadata": {}}).to_string();
+        let previous = facade(
+            "yap-notebook-previous.ipynb",
+            &notebook(json!([
+                {"cell_type": "markdown", "source": ["# Title"]},
+                {"cell_type": "code", "source": "import yap", "execution_count": 1, "outputs": []},
+                {"cell_type": "code", "source": ["x = 1\n", "x"], "execution_count": 2,
+                    "outputs": [{"output_type": "execute_result", "execution_count": 2, "data": {"text/plain": "1"}}]},
+                {"cell_type": "code", "source": "print(x)", "outputs": []},
+                {"cell_type": "code", "source": "y = 3", "outputs": []},
+            ])),
+        );
+        let current = facade(
+            "yap-notebook-current.ipynb",
+            &notebook(json!([
+                {"cell_type": "code", "source": "y = 3", "outputs": []},
+                {"cell_type": "markdown", "source": ["# Title"]},
+                {"cell_type": "code", "source": ["x = 2\n", "x"], "execution_count": 7,
+                    "outputs": [{"output_type": "execute_result", "execution_count": 7, "data": {"text/plain": "2"}}]},
+                {"cell_type": "code", "source": "import yap", "execution_count": 5, "outputs": []},
+            ])),
+        );
+        let comparaison =
+            Comparaison::new(&ComparaisonTechnique::Notebook, &None).set_outputs(true);
+        let result = comparaison.compare_notebook(&current, &previous).unwrap();
+        let result = &result["notebook"];
+        assert_eq!(result["moved"], json!([{"previous": 4, "current": 0}]));
+        assert_eq!(result["edited"][0]["previous"], 2);
+        assert_eq!(result["edited"][0]["current"], 2);
+        assert_eq!(result["removed"], json!([3]));
+        assert_eq!(result["added"], json!([]));
+        assert_eq!(result["outputs"].as_array().unwrap().len(), 1);
+
+        let broken = facade("yap-notebook-broken.ipynb", r#"{"metadata": {}}"#);
+        assert!(comparaison.compare_notebook(&broken, &previous).is_err());
+    }
+
+    #[test]
+    fn test_compare_tabular() {
+        let previous = facade(
+            "yap-tabular-previous.csv",
+            "id,name,value\n1,a,10\n2,b,20\n3,c,30\n",
+        );
+        let current = facade(
+            "yap-tabular-current.csv",
+            "id,value,extra\n1,10,x\n2,25,y\n4,40,z\n",
+        );
+        let result = Comparaison::new(&ComparaisonTechnique::Tabular, &None)
+            .set_key(&Some("id".to_string()))
+            .compare_tabular(&current, &previous)
+            .unwrap();
+        let result = &result["tabular"];
+        assert_eq!(result["schema"]["added"], json!(["extra"]));
+        assert_eq!(result["schema"]["removed"], json!(["name"]));
+        assert_eq!(result["rows"]["delta"], 0);
+        assert_eq!(result["statistics"]["value"]["delta"]["max"], 10.0);
+        assert_eq!(result["changes"]["inserted"], 1);
+        assert_eq!(result["changes"]["deleted"], 1);
+        assert_eq!(result["changes"]["updated"], 1);
+        assert_eq!(result["changes"]["sample"]["updated"][0]["key"], "2");
+
+        let previous = facade("yap-tabular-keyless-previous.csv", "a,b\n1,x\n2,y\n3,z\n");
+        let current = facade(
+            "yap-tabular-keyless-current.csv",
+            "a,b\n1,x\n9,new\n2,y\n3,z\n3,z\n",
+        );
+        let result = Comparaison::new(&ComparaisonTechnique::Tabular, &None)
+            .compare_tabular(&current, &previous)
+            .unwrap();
+        let changes = &result["tabular"]["changes"];
+        assert_eq!(changes["inserted"], 2);
+        assert_eq!(changes["deleted"], 0);
+        assert_eq!(changes["updated"], 0);
+
+        let result = Comparaison::new(&ComparaisonTechnique::Tabular, &None)
+            .set_key(&Some("missing".to_string()))
+            .compare_tabular(&current, &previous);
+        assert!(result.is_err());
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{name}", std::process::id()))
+    }
+
+    fn facade(name: &str, content: impl AsRef<[u8]>) -> FileFacade {
+        let path = temp_path(name);
+        fs::write(&path, content).unwrap();
+        FileFacade::new(File::new(&path, "master", "", 0, Author::default()))
+    }
+
+    fn script(name: &str, body: &str) -> PathBuf {
+        let path = temp_path(name);
+        fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_compare_custom_parses_stdout() {
+        let script = script(
+            "yap-custom-ok.sh",
+            r#"echo "{\"from\": \"$1\", \"lines\": 2}""#,
+        );
+        let previous = facade("yap-custom-previous.txt", "a\n");
+        let current = facade("yap-custom-current.txt", "a\nb\n");
+        let result =
+            Comparaison::new(&ComparaisonTechnique::Custom, &Some(script.clone()))
+                .compare_custom(&current, &previous, &script)
+                .await
+                .unwrap();
+        assert_eq!(result["lines"], 2);
+        assert_eq!(result["from"], previous.content_path().to_str().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_compare_custom_failures() {
+        let previous = facade("yap-custom-failures-previous.txt", "a\n");
+        let current = facade("yap-custom-failures-current.txt", "b\n");
+
+        let failing = script("yap-custom-fail.sh", "echo broken >&2; exit 3");
+        let result = Comparaison::new(&ComparaisonTechnique::Custom, &None)
+            .compare_custom(&current, &previous, &failing)
+            .await
+            .unwrap_err();
+        assert_eq!(result["code"], 3);
+        assert_eq!(result["stderr"], "broken\n");
+
+        let slow = script("yap-custom-slow.sh", "sleep 5");
+        let result = Comparaison::new(&ComparaisonTechnique::Custom, &None)
+            .set_timeout(1)
+            .compare_custom(&current, &previous, &slow)
+            .await
+            .unwrap_err();
+        assert!(result["error"].as_str().unwrap().contains("timed out"));
     }
 }
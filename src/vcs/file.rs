@@ -1,10 +1,14 @@
-use crate::config::{Author, Config, PushStrategy, RemoteConfig, Storage};
+use crate::{
+    config::{Author, Config, PushStrategy, RemoteConfig, Storage},
+    schemas::{migrate, FILE_MIGRATIONS, ROOT_MIGRATIONS},
+};
 use futures::stream::StreamExt;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar};
 use libsql::{params, Builder, Connection, Database};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     env,
     fmt::Debug,
     os::unix::fs::MetadataExt,
@@ -12,10 +16,17 @@ use std::{
 };
 
 use super::{
+    branch::Branch,
     cli::Events,
-    comparaison::{Comparaison, ComparaisonTechnique},
-    remote::{pull_file, push_file},
-    versioning::{get_latest_git_commit, Commit},
+    comparaison::{Comparaison, Diff},
+    ignores::IgnoreRules,
+    objects::{hash_file, hashing_progress, progress_style, Chunk, ObjectStore},
+    remote::{fetch_object, pull_file, push_file, remove_file},
+    tag::Tag,
+    versioning::{
+        get_latest_git_commit, Commit, FileStatus, LogEntry, LogFilter, StatusEntry,
+        VersionRef,
+    },
 };
 
 pub struct Logbook {
@@ -36,7 +47,9 @@ impl Logbook {
             .build()
             .await
             .expect("unable to open local");
-        Self::new(db)
+        let logbook = Self::new(db);
+        migrate(&logbook.conn(), &ROOT_MIGRATIONS).await;
+        logbook
     }
 
     pub async fn file_is_tracked(&self, file: &FileFacade) -> bool {
@@ -94,6 +107,315 @@ impl Logbook {
             .expect("error erting the newly tracked file");
     }
 
+    pub async fn files_tracked_in(&self, branch: &str) -> Vec<String> {
+        self.conn()
+            .query("SELECT path FROM files WHERE branch=?1", params![branch])
+            .await
+            .unwrap()
+            .into_stream()
+            .map(|f| f.unwrap().get::<String>(0).unwrap())
+            .collect()
+            .await
+    }
+
+    /// Create the branch, or bring a deleted one back
+    pub async fn create_branch(&self, name: &str, description: &str, author: &Author) {
+        self.conn()
+            .execute(
+                "INSERT INTO branches (name, description, author) VALUES (?1, ?2, ?3) ON CONFLICT (name) DO UPDATE SET description=excluded.description, author=excluded.author, status='Active', updated_at=CURRENT_TIMESTAMP",
+                params![name, description, author.pk()],
+            )
+            .await
+            .unwrap_or_else(|err| panic!("error creating the branch {name}: {err}"));
+    }
+
+    pub async fn branch_exists(&self, name: &str) -> bool {
+        let mut result = self
+            .conn()
+            .query(
+                "SELECT EXISTS(SELECT 1 FROM branches WHERE name=?1 AND status IS NOT 'Deleted' UNION SELECT 1 FROM files WHERE branch=?1 AND branch NOT IN (SELECT name FROM branches));",
+                params![name],
+            )
+            .await
+            .expect("error checking if the branch exists");
+        0 != result
+            .next()
+            .await
+            .expect("iterator empty")
+            .expect("empyt")
+            .get::<u32>(0)
+            .expect("couldnt get the value")
+    }
+
+    /// Branches created with yap plus the ones only known from the tracked files, like the
+    /// git branches used before creating any
+    pub async fn branches(&self) -> Vec<Branch> {
+        self.conn()
+            .query(
+                "SELECT name, description, status, author, created_at FROM branches WHERE status IS NOT 'Deleted' UNION SELECT DISTINCT branch, '', 'Active', '', '' FROM files WHERE branch NOT IN (SELECT name FROM branches) ORDER BY 1",
+                (),
+            )
+            .await
+            .expect("error reading the branches")
+            .into_stream()
+            .map(|row| {
+                let row = row.unwrap();
+                Branch {
+                    name: row.get::<String>(0).unwrap(),
+                    description: row.get::<Option<String>>(1).unwrap().unwrap_or_default(),
+                    status: row.get::<Option<String>>(2).unwrap().unwrap_or_default(),
+                    author: row.get::<String>(3).unwrap(),
+                    created_at: row.get::<Option<String>>(4).unwrap().unwrap_or_default(),
+                }
+            })
+            .collect()
+            .await
+    }
+
+    pub async fn set_branch_status(&self, name: &str, status: &str) {
+        self.conn()
+            .execute(
+                "UPDATE branches SET status=?1, updated_at=CURRENT_TIMESTAMP WHERE name=?2",
+                params![status, name],
+            )
+            .await
+            .unwrap_or_else(|err| panic!("error updating the branch {name}: {err}"));
+    }
+
+    /// Mark the branch as deleted. Its files stay tracked so the garbage collection still
+    /// keeps their versions and they can be restored.
+    pub async fn delete_branch(&self, name: &str, author: &Author) {
+        self.conn()
+            .execute(
+                "INSERT INTO branches (name, status, author) VALUES (?1, 'Deleted', ?2) ON CONFLICT (name) DO UPDATE SET status='Deleted', updated_at=CURRENT_TIMESTAMP",
+                params![name, author.pk()],
+            )
+            .await
+            .unwrap_or_else(|err| panic!("error deleting the branch {name}: {err}"));
+    }
+
+    pub async fn create_tag(&self, tag: &Tag, versions: &[(PathBuf, String)]) {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO tags (name, message, branch, author) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                tag.name.clone(),
+                tag.message.clone(),
+                tag.branch.clone(),
+                tag.author.clone()
+            ],
+        )
+        .await
+        .unwrap_or_else(|err| panic!("error creating the tag {}: {err}", tag.name));
+        for (path, object) in versions {
+            conn.execute(
+                "INSERT INTO tag_versions (tag, path, object) VALUES (?1, ?2, ?3)",
+                params![
+                    tag.name.clone(),
+                    path.to_str().expect("unable to convert to str"),
+                    object.clone()
+                ],
+            )
+            .await
+            .unwrap_or_else(|err| panic!("error tagging {:?}: {err}", path));
+        }
+    }
+
+    pub async fn tag_exists(&self, name: &str) -> bool {
+        let mut result = self
+            .conn()
+            .query(
+                "SELECT EXISTS(SELECT 1 FROM tags WHERE name=?1);",
+                params![name],
+            )
+            .await
+            .expect("error checking if the tag exists");
+        0 != result
+            .next()
+            .await
+            .expect("iterator empty")
+            .expect("empyt")
+            .get::<u32>(0)
+            .expect("couldnt get the value")
+    }
+
+    pub async fn tags(&self) -> Vec<Tag> {
+        self.conn()
+            .query(
+                "SELECT t.name, t.message, t.branch, t.author, t.created_at, COUNT(v.id) FROM tags t LEFT JOIN tag_versions v ON v.tag = t.name GROUP BY t.name ORDER BY t.created_at, t.id",
+                (),
+            )
+            .await
+            .expect("error reading the tags")
+            .into_stream()
+            .map(|row| {
+                let row = row.unwrap();
+                Tag {
+                    name: row.get::<String>(0).unwrap(),
+                    message: row.get::<Option<String>>(1).unwrap().unwrap_or_default(),
+                    branch: row.get::<String>(2).unwrap(),
+                    author: row.get::<String>(3).unwrap(),
+                    created_at: row.get::<Option<String>>(4).unwrap().unwrap_or_default(),
+                    files: row.get::<u32>(5).unwrap(),
+                }
+            })
+            .collect()
+            .await
+    }
+
+    /// Path and object of every version recorded by the tag
+    pub async fn tag_versions(&self, name: &str) -> Vec<(PathBuf, String)> {
+        self.conn()
+            .query(
+                "SELECT path, object FROM tag_versions WHERE tag=?1 ORDER BY path",
+                params![name],
+            )
+            .await
+            .expect("error reading the tagged versions")
+            .into_stream()
+            .map(|row| {
+                let row = row.unwrap();
+                (
+                    PathBuf::from(row.get::<String>(0).unwrap()),
+                    row.get::<String>(1).unwrap(),
+                )
+            })
+            .collect()
+            .await
+    }
+
+    /// Objects of the file kept by any tag
+    pub async fn tagged_objects(&self, path: &Path) -> HashSet<String> {
+        self.conn()
+            .query(
+                "SELECT object FROM tag_versions WHERE path=?1",
+                params![path.to_str().expect("unable to convert to str")],
+            )
+            .await
+            .expect("error reading the tagged versions")
+            .into_stream()
+            .map(|row| row.unwrap().get::<String>(0).unwrap())
+            .collect()
+            .await
+    }
+
+    pub async fn delete_tag(&self, name: &str) {
+        let conn = self.conn();
+        conn.execute("DELETE FROM tag_versions WHERE tag=?1", params![name])
+            .await
+            .unwrap_or_else(|err| panic!("error deleting the tag {name}: {err}"));
+        conn.execute("DELETE FROM tags WHERE name=?1", params![name])
+            .await
+            .unwrap_or_else(|err| panic!("error deleting the tag {name}: {err}"));
+    }
+
+    /// Start a repository commit. It stays pending until every file version is recorded.
+    pub async fn begin_commit(
+        &self,
+        timestamp: i64,
+        message: &str,
+        branch: &str,
+        author: &Author,
+        git_commit: &str,
+    ) -> Result<u32, String> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO commits (timestamp, message, branch, author, git_commit) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![timestamp, message, branch, author.pk(), git_commit],
+        )
+        .await
+        .map_err(|err| format!("unable to start the commit: {err}"))?;
+        Ok(conn.last_insert_rowid() as u32)
+    }
+
+    pub async fn add_commit_file(
+        &self,
+        id: u32,
+        path: &Path,
+        object: &str,
+    ) -> Result<(), String> {
+        self.conn()
+            .execute(
+                "INSERT INTO commit_files (root_commit, path, object) VALUES (?1, ?2, ?3)",
+                params![id, path.to_str().expect("unable to convert to str"), object],
+            )
+            .await
+            .map(|_| ())
+            .map_err(|err| format!("unable to add {:?} to the commit {id}: {err}", path))
+    }
+
+    pub async fn complete_commit(&self, id: u32) {
+        self.conn()
+            .execute(
+                "UPDATE commits SET status='Committed' WHERE id=?1",
+                params![id],
+            )
+            .await
+            .unwrap_or_else(|err| panic!("error completing the commit {id}: {err}"));
+    }
+
+    pub async fn delete_commit(&self, id: u32) {
+        let conn = self.conn();
+        conn.execute("DELETE FROM commit_files WHERE root_commit=?1", params![id])
+            .await
+            .unwrap_or_else(|err| panic!("error deleting the commit {id}: {err}"));
+        conn.execute("DELETE FROM commits WHERE id=?1", params![id])
+            .await
+            .unwrap_or_else(|err| panic!("error deleting the commit {id}: {err}"));
+    }
+
+    /// Record the prepared files under the repository commit. If one of them fails the rows
+    /// already written are deleted along with the commit.
+    pub async fn record_commit(
+        &self,
+        id: u32,
+        files: &mut [FileFacade],
+    ) -> Result<(), String> {
+        let mut failure = None;
+        for file in files.iter_mut() {
+            let recorded = match file.record(id).await {
+                Ok(()) => {
+                    self.add_commit_file(id, file.path(), &file.file().object())
+                        .await
+                },
+                Err(err) => Err(err),
+            };
+            if let Err(err) = recorded {
+                failure = Some(err);
+                break;
+            }
+        }
+        let Some(err) = failure else {
+            return Ok(());
+        };
+        for file in files.iter_mut() {
+            file.rollback().await;
+        }
+        self.delete_commit(id).await;
+        Err(err)
+    }
+
+    /// Path and object of every file version of a completed commit
+    pub async fn commit_files(&self, id: u32) -> Vec<(PathBuf, String)> {
+        self.conn()
+            .query(
+                "SELECT f.path, f.object FROM commit_files f JOIN commits c ON c.id = f.root_commit WHERE c.id=?1 AND c.status='Committed' ORDER BY f.path",
+                params![id],
+            )
+            .await
+            .expect("error reading the commited files")
+            .into_stream()
+            .map(|row| {
+                let row = row.unwrap();
+                (
+                    PathBuf::from(row.get::<String>(0).unwrap()),
+                    row.get::<String>(1).unwrap(),
+                )
+            })
+            .collect()
+            .await
+    }
+
     pub async fn files_tracked(&self) -> Vec<String> {
         self.db
             .connect()
@@ -120,6 +442,9 @@ pub struct FileFacadeFactory {
     comparaison: Option<Comparaison>,
     message: Option<String>,
     stack: VecDeque<PathBuf>,
+    chunking_threshold: Option<u64>,
+    ignores: IgnoreRules,
+    multi_progress: Option<MultiProgress>,
 }
 
 impl FileFacadeFactory {
@@ -131,6 +456,8 @@ impl FileFacadeFactory {
             author: config.author(),
             timestamp: chrono::offset::Local::now().timestamp(),
             stack: VecDeque::from(paths),
+            chunking_threshold: config.chunking_threshold(),
+            ignores: IgnoreRules::new(config.root_dir(), config.ignore_patterns()),
             ..Self::default()
         }
     }
@@ -152,12 +479,8 @@ impl FileFacadeFactory {
         self
     }
 
-    pub fn set_comparaison(
-        mut self,
-        technique: &ComparaisonTechnique,
-        path: &Option<PathBuf>,
-    ) -> Self {
-        self.comparaison = Some(Comparaison::new(technique, path));
+    pub fn set_comparaison(mut self, comparaison: Comparaison) -> Self {
+        self.comparaison = Some(comparaison);
         self
     }
 
@@ -166,6 +489,12 @@ impl FileFacadeFactory {
         self
     }
 
+    /// Draw the progress bars of the files handled concurrently together
+    pub fn set_multi_progress(mut self, progress: &MultiProgress) -> Self {
+        self.multi_progress = Some(progress.clone());
+        self
+    }
+
     fn to_facade(&self, path: &Path) -> FileFacade {
         let file = File::new(
             path,
@@ -175,7 +504,12 @@ impl FileFacadeFactory {
             self.author.clone(),
         );
         let logbook = FileLogbook::new(path, &self.logbooks_dir);
-        let facade = FileFacade::new(file).set_logbook(logbook);
+        let mut facade = FileFacade::new(file)
+            .set_logbook(logbook)
+            .set_chunking_threshold(self.chunking_threshold);
+        if let Some(progress) = &self.multi_progress {
+            facade = facade.set_multi_progress(progress);
+        }
         match (self.comparaison.is_some(), self.remote.is_some()) {
             // We are pushing so we need the remote info
             (false, true) => facade.set_remote(self.remote.as_ref().unwrap()),
@@ -183,23 +517,35 @@ impl FileFacadeFactory {
             (true, false) => facade.set_comparaison(self.comparaison.as_ref().unwrap()),
             // We are just adding files
             (false, false) => facade,
-            // IDK what this could be
-            _ => todo!("why have we comparaison and remote"),
+            // We are comparing versions that may only be in the remote
+            (true, true) => facade
+                .set_remote(self.remote.as_ref().unwrap())
+                .set_comparaison(self.comparaison.as_ref().unwrap()),
         }
     }
 }
 
 impl Iterator for FileFacadeFactory {
     type Item = FileFacade;
+    /// Files of the given paths, walking into the directories. The entries found while
+    /// walking are skipped when ignored, the given paths never are.
     fn next(&mut self) -> Option<Self::Item> {
-        self.stack.pop_front().map(|p| {
-            if p.is_dir() {
-                p.read_dir()
-                    .unwrap()
-                    .for_each(|p| self.stack.push_back(p.unwrap().path()));
+        while let Some(p) = self.stack.pop_front() {
+            if !p.is_dir() {
+                return Some(self.to_facade(&p));
             }
-            self.to_facade(&p)
-        })
+            for entry in p.read_dir().unwrap() {
+                let entry = entry.unwrap().path();
+                let entry = entry
+                    .strip_prefix("./")
+                    .map(Path::to_path_buf)
+                    .unwrap_or(entry);
+                if !self.ignores.is_ignored(&entry) {
+                    self.stack.push_back(entry);
+                }
+            }
+        }
+        None
     }
 }
 
@@ -220,10 +566,6 @@ impl Remote {
             storage,
         }
     }
-
-    pub fn get(_path: PathBuf, _storage: Storage) -> Self {
-        todo!()
-    }
 }
 
 impl LogbookProvider for Remote {
@@ -248,6 +590,10 @@ pub struct File {
     history: String,
     branch: String,
     timestamp: i64,
+    object: Option<String>,
+    // Size and modification time in nanoseconds of the working file the object was made from
+    size: u64,
+    modified: i64,
 }
 
 impl File {
@@ -266,37 +612,65 @@ impl File {
             timestamp,
             author,
             remote: None,
+            object: None,
+            size: 0,
+            modified: 0,
         }
     }
 
+    pub fn set_object(mut self, object: &str) -> Self {
+        self.object = Some(object.to_owned());
+        self
+    }
+
+    pub fn object(&self) -> String {
+        self.object.clone().unwrap_or_default()
+    }
+
+    fn set_metadata(&mut self, metadata: &std::fs::Metadata) -> &Self {
+        self.size = metadata.size();
+        self.modified = metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec();
+        self
+    }
+
+    pub fn branch(&self) -> &str {
+        &self.branch
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
     fn set_remote(&mut self, remote: Remote) -> &Self {
         self.remote = Some(remote);
         self
     }
 
+    /// The path is relative to the repository root, which the vcs commands work from
     pub fn original_path(&self) -> PathBuf {
-        //TODO: will path always be relative?
         env::current_dir().unwrap().join(&self.path)
     }
 
-    pub fn history_dir(&self) -> PathBuf {
-        env::current_dir()
-            .unwrap()
-            .join(&self.history)
-            .join(&self.path)
-            .join(&self.branch)
+    pub fn store(&self) -> ObjectStore {
+        ObjectStore::new(&env::current_dir().unwrap().join(&self.history))
     }
 
     pub fn history_path(&self) -> PathBuf {
-        //TODO: will path always be relative? probably not
-        self.history_dir()
-            .join(Path::new(&self.timestamp.to_string()))
+        self.store().object_path(
+            self.object
+                .as_ref()
+                .expect("the version has not been saved in the history yet"),
+        )
     }
 }
 
 impl LogbookProvider for File {
     async fn query(&self) -> String {
-        "INSERT INTO files (timestamp, path, branch, author) VALUES (?1, ?2, ?3, ?4)"
+        "INSERT INTO files (timestamp, path, branch, author, object, size, modified) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
             .to_string()
     }
     async fn params(&self) -> Vec<String> {
@@ -305,6 +679,9 @@ impl LogbookProvider for File {
             self.path.to_str().unwrap().to_owned(),
             self.branch.to_owned(),
             self.author.pk(), //TODO: add the authors all over the place
+            self.object(),
+            self.size.to_string(),
+            self.modified.to_string(),
         ]
     }
 }
@@ -335,21 +712,7 @@ impl FileLogbook {
             .await
             .expect("unable to open local db");
         self.db = Some(db);
-        self
-    }
-
-    pub async fn create(&self) -> &Self {
-        let query = match tokio::fs::read_to_string("schemas/file_logbook.sql").await {
-            Ok(sql) => sql,
-            Err(err) => {
-                panic!("{err}");
-            },
-        };
-        self.conn()
-            .await
-            .execute_batch(&query)
-            .await
-            .expect("unable to create tables into file logbook");
+        migrate(&self.conn().await, &FILE_MIGRATIONS).await;
         self
     }
 
@@ -365,6 +728,369 @@ impl FileLogbook {
         object
     }
 
+    /// Same as insert but returns the primary key of the new row
+    pub async fn save<T: LogbookProvider + Debug>(&self, object: &T) -> u32 {
+        self.try_save(object)
+            .await
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub async fn try_save<T: LogbookProvider + Debug>(
+        &self,
+        object: &T,
+    ) -> Result<u32, String> {
+        let conn = self.conn().await;
+        conn.execute(&object.query().await, object.params().await)
+            .await
+            .map_err(|err| {
+                format!(
+                    "unable to save movement into project logbook {:?}: {err}",
+                    object
+                )
+            })?;
+        Ok(conn.last_insert_rowid() as u32)
+    }
+
+    pub async fn delete_row(&self, table: &str, id: u32) {
+        self.conn()
+            .await
+            .execute(&format!("DELETE FROM {table} WHERE id=?1"), params![id])
+            .await
+            .unwrap_or_else(|err| {
+                panic!("unable to delete the row {id} of {table}: {err}")
+            });
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub async fn log(&self, file: &File, filter: &LogFilter) -> Vec<LogEntry> {
+        self.conn()
+            .await
+            .query(
+                "SELECT c.id, c.timestamp, c.author, c.branch, c.git_commit, c.message, d.technique, d.result, c.root_commit FROM commits c LEFT JOIN diffs d ON d.id = c.diff WHERE (?1 = '' OR c.branch = ?1) AND (?2 = '' OR c.author LIKE '%' || ?2 || '%') AND c.timestamp >= ?3 AND c.timestamp <= ?4 ORDER BY c.timestamp DESC, c.id DESC",
+                params![
+                    filter.branch.clone().unwrap_or_default(),
+                    filter.author.clone().unwrap_or_default(),
+                    filter.since.unwrap_or(i64::MIN),
+                    filter.until.unwrap_or(i64::MAX),
+                ],
+            )
+            .await
+            .expect("unable to read the commits")
+            .into_stream()
+            .map(|row| {
+                let row = row.unwrap();
+                LogEntry {
+                    id: row.get::<u32>(0).unwrap(),
+                    root_commit: row.get::<Option<u32>>(8).unwrap().unwrap_or_default(),
+                    path: file.path.clone(),
+                    timestamp: row.get::<i64>(1).unwrap(),
+                    author: row.get::<String>(2).unwrap(),
+                    branch: row.get::<String>(3).unwrap(),
+                    git_commit: row.get::<Option<String>>(4).unwrap().unwrap_or_default(),
+                    message: row.get::<Option<String>>(5).unwrap().unwrap_or_default(),
+                    technique: row.get::<Option<String>>(6).unwrap(),
+                    diff: row
+                        .get::<Option<String>>(7)
+                        .unwrap()
+                        .and_then(|d| serde_json::from_str(&d).ok()),
+                }
+            })
+            .collect()
+            .await
+    }
+
+    fn version_from_row(file: &File, row: &libsql::Row) -> File {
+        File::new(
+            &file.path,
+            &file.branch,
+            &file.history,
+            row.get::<i64>(0).unwrap(),
+            file.author.clone(),
+        )
+        .set_object(&row.get::<String>(1).unwrap())
+    }
+
+    pub async fn latest_version(&self, file: &File) -> Option<File> {
+        let mut rows = self
+            .conn()
+            .await
+            .query(
+                "SELECT timestamp, object FROM files WHERE path=?1 AND branch=?2 AND object != '' ORDER BY timestamp DESC, id DESC LIMIT 1",
+                params![file.path.to_str().unwrap(), file.branch.clone()],
+            )
+            .await
+            .expect("unable to query the latest version");
+        rows.next()
+            .await
+            .expect("unable to read the latest version")
+            .map(|row| Self::version_from_row(file, &row))
+    }
+
+    pub async fn find_version(&self, file: &File, version: &VersionRef) -> Option<File> {
+        let conn = self.conn().await;
+        let path = file.path.to_str().unwrap();
+        let rows = match version {
+            VersionRef::Latest => return self.latest_version(file).await,
+            VersionRef::Working => return None,
+            VersionRef::Object(object) => return Some(file.clone().set_object(object)),
+            VersionRef::Commit(id) => {
+                conn.query(
+                    "SELECT timestamp, object FROM files WHERE path=?1 AND object=(SELECT file_to FROM commits WHERE root_commit=?2 ORDER BY id DESC LIMIT 1) ORDER BY timestamp DESC, id DESC LIMIT 1",
+                    params![path, *id],
+                )
+                .await
+            },
+            VersionRef::Timestamp(timestamp) => {
+                conn.query(
+                    "SELECT timestamp, object FROM files WHERE path=?1 AND branch=?2 AND object != '' AND timestamp<=?3 ORDER BY timestamp DESC, id DESC LIMIT 1",
+                    params![path, file.branch.clone(), *timestamp],
+                )
+                .await
+            },
+            VersionRef::GitCommit(sha) => {
+                conn.query(
+                    "SELECT timestamp, object FROM files WHERE path=?1 AND object=(SELECT file_to FROM commits WHERE branch=?2 AND git_commit LIKE ?3 ORDER BY id DESC LIMIT 1) ORDER BY timestamp DESC, id DESC LIMIT 1",
+                    params![path, file.branch.clone(), format!("{sha}%")],
+                )
+                .await
+            },
+        };
+        rows.expect("unable to query the version")
+            .next()
+            .await
+            .expect("unable to read the version")
+            .map(|row| Self::version_from_row(file, &row))
+    }
+
+    /// Whether a commit recorded the version but the garbage collection removed it since
+    pub async fn collected(&self, file: &File, version: &VersionRef) -> bool {
+        let conn = self.conn().await;
+        let rows = match version {
+            VersionRef::Commit(id) => {
+                conn.query(
+                    "SELECT EXISTS(SELECT 1 FROM commits WHERE root_commit=?1);",
+                    params![*id],
+                )
+                .await
+            },
+            VersionRef::GitCommit(sha) => {
+                conn.query(
+                    "SELECT EXISTS(SELECT 1 FROM commits WHERE branch=?1 AND git_commit LIKE ?2);",
+                    params![file.branch.clone(), format!("{sha}%")],
+                )
+                .await
+            },
+            _ => return false,
+        };
+        0 != rows
+            .expect("unable to query the commits")
+            .next()
+            .await
+            .expect("unable to read the commits")
+            .map(|row| row.get::<u32>(0).unwrap())
+            .unwrap_or_default()
+    }
+
+    /// Technique and result of the latest diff recorded between the two versions
+    pub async fn stored_diff(&self, from: &File, to: &File) -> Option<(String, Value)> {
+        let mut rows = self
+            .conn()
+            .await
+            .query(
+                "SELECT technique, result FROM diffs WHERE file_from=?1 AND file_to=?2 ORDER BY id DESC LIMIT 1",
+                params![from.object(), to.object()],
+            )
+            .await
+            .expect("unable to query the diffs");
+        rows.next()
+            .await
+            .expect("unable to read the diff")
+            .map(|row| {
+                (
+                    row.get::<String>(0).unwrap(),
+                    serde_json::from_str(&row.get::<String>(1).unwrap_or_default())
+                        .unwrap_or(Value::Null),
+                )
+            })
+    }
+
+    /// Every version recorded for the file in its branch, from the oldest to the newest
+    pub async fn versions(&self, file: &File) -> Vec<File> {
+        self.conn()
+            .await
+            .query(
+                "SELECT timestamp, object FROM files WHERE path=?1 AND branch=?2 AND object != '' ORDER BY timestamp ASC, id ASC",
+                params![file.path.to_str().unwrap(), file.branch.clone()],
+            )
+            .await
+            .expect("unable to query the versions")
+            .into_stream()
+            .map(|row| Self::version_from_row(file, &row.unwrap()))
+            .collect()
+            .await
+    }
+
+    /// Object recorded for a working file with the same size and modification time, so an
+    /// untouched file is never hashed again
+    pub async fn cached_object(&self, file: &File) -> Option<String> {
+        let mut rows = self
+            .conn()
+            .await
+            .query(
+                "SELECT object FROM files WHERE path=?1 AND size=?2 AND modified=?3 AND modified != 0 AND object != '' ORDER BY id DESC LIMIT 1",
+                params![file.path.to_str().unwrap(), file.size as i64, file.modified],
+            )
+            .await
+            .expect("unable to query the recorded hashes");
+        rows.next()
+            .await
+            .expect("unable to read the recorded hash")
+            .map(|row| row.get::<String>(0).unwrap())
+    }
+
+    pub async fn save_manifest(&self, object: &str, chunks: &[Chunk]) {
+        let conn = self.conn().await;
+        for (position, chunk) in chunks.iter().enumerate() {
+            conn.execute(
+                "INSERT OR IGNORE INTO chunks (object, position, chunk, size) VALUES (?1, ?2, ?3, ?4)",
+                params![object, position as i64, chunk.id.clone(), chunk.size as i64],
+            )
+            .await
+            .expect("unable to save the chunk manifest");
+        }
+    }
+
+    /// Chunks of the object in order, empty when it is stored whole
+    pub async fn manifest(&self, object: &str) -> Vec<Chunk> {
+        self.conn()
+            .await
+            .query(
+                "SELECT chunk, size FROM chunks WHERE object=?1 ORDER BY position ASC",
+                params![object],
+            )
+            .await
+            .expect("unable to query the chunk manifest")
+            .into_stream()
+            .map(|row| {
+                let row = row.unwrap();
+                Chunk {
+                    id: row.get::<String>(0).unwrap(),
+                    size: row.get::<i64>(1).unwrap() as u64,
+                }
+            })
+            .collect()
+            .await
+    }
+
+    /// Every version of the file whatever its branch, from the newest to the oldest
+    pub async fn all_versions(&self, file: &File) -> Vec<File> {
+        self.conn()
+            .await
+            .query(
+                "SELECT timestamp, object, branch FROM files WHERE path=?1 AND object != '' ORDER BY timestamp DESC, id DESC",
+                params![file.path.to_str().unwrap()],
+            )
+            .await
+            .expect("unable to query the versions")
+            .into_stream()
+            .map(|row| {
+                let row = row.unwrap();
+                let mut version = Self::version_from_row(file, &row);
+                version.branch = row.get::<String>(2).unwrap();
+                version
+            })
+            .collect()
+            .await
+    }
+
+    /// Objects of the versions commited along a git commit
+    pub async fn git_objects(&self) -> HashSet<String> {
+        self.objects_of(
+            "SELECT file_to FROM commits WHERE git_commit IS NOT NULL AND git_commit != ''",
+        )
+        .await
+    }
+
+    async fn objects_of(&self, query: &str) -> HashSet<String> {
+        self.conn()
+            .await
+            .query(query, ())
+            .await
+            .expect("unable to query the objects")
+            .into_stream()
+            .map(|row| row.unwrap().get::<String>(0).unwrap())
+            .collect()
+            .await
+    }
+
+    /// Remove the versions and the manifests of the objects. Commits and diffs are kept so
+    /// the log still tells what happened.
+    pub async fn forget(&self, objects: &HashSet<String>) {
+        let conn = self.conn().await;
+        for object in objects {
+            conn.execute("DELETE FROM files WHERE object=?1", params![object.clone()])
+                .await
+                .expect("unable to remove the version");
+            conn.execute(
+                "DELETE FROM chunks WHERE object=?1",
+                params![object.clone()],
+            )
+            .await
+            .expect("unable to remove the manifest");
+        }
+    }
+
+    pub async fn has_version(&self, file: &File) -> bool {
+        let mut rows = self
+            .conn()
+            .await
+            .query(
+                "SELECT EXISTS(SELECT 1 FROM files WHERE branch=?1 AND object=?2 LIMIT 1);",
+                params![file.branch.clone(), file.object()],
+            )
+            .await
+            .expect("unable to check the version");
+        0 != rows
+            .next()
+            .await
+            .expect("iterator empty")
+            .expect("empyt")
+            .get::<u32>(0)
+            .expect("couldnt get the value")
+    }
+
+    pub async fn unpushed(&self, file: &File) -> u32 {
+        let mut rows = self
+            .conn()
+            .await
+            .query(
+                "SELECT COUNT(DISTINCT object) FROM files WHERE path=?1 AND branch=?2 AND object != '' AND remote = ''",
+                params![file.path.to_str().unwrap(), file.branch.clone()],
+            )
+            .await
+            .expect("unable to count the unpushed versions");
+        rows.next()
+            .await
+            .expect("iterator empty")
+            .expect("empyt")
+            .get::<u32>(0)
+            .expect("couldnt get the value")
+    }
+
+    pub async fn mark_pushed(&self, file: &File, storage: &Storage) {
+        self.conn()
+            .await
+            .execute(
+                "UPDATE files SET remote=?1 WHERE object=?2",
+                params![storage.to_string(), file.object()],
+            )
+            .await
+            .expect("unable to mark the version as pushed");
+    }
+
     async fn conn(&self) -> Connection {
         self.db
             .as_ref()
@@ -387,6 +1113,11 @@ pub struct FileFacade {
     remote: Option<RemoteConfig>,
     comparaison: Option<Comparaison>,
     progress_bar: Option<ProgressBar>,
+    multi_progress: Option<MultiProgress>,
+    chunking_threshold: Option<u64>,
+    // Commit prepared for the working file and the rows recorded for it so far
+    commit: Option<Commit>,
+    recorded: Vec<(&'static str, u32)>,
 }
 
 impl FileFacade {
@@ -398,23 +1129,42 @@ impl FileFacade {
             remote: None,
             comparaison: None,
             progress_bar: None,
+            multi_progress: None,
+            chunking_threshold: None,
+            commit: None,
+            recorded: Vec::new(),
         }
     }
 
+    pub fn set_chunking_threshold(mut self, threshold: Option<u64>) -> Self {
+        self.chunking_threshold = threshold;
+        self
+    }
+
     pub async fn init(mut self) -> Self {
         self.logbook.init().await;
         self
     }
 
+    pub fn set_multi_progress(mut self, progress: &MultiProgress) -> Self {
+        self.multi_progress = Some(progress.clone());
+        self
+    }
+
+    /// Register the progress bar on the shared one so concurrent bars don't overwrite
+    /// each other
+    fn show_progress(&self, progress: ProgressBar) -> ProgressBar {
+        match &self.multi_progress {
+            Some(multi) => multi.add(progress),
+            None => progress,
+        }
+    }
+
     fn set_progress_bar(&mut self, len: u64, msg: &str) -> &Self {
-        let progress = ProgressBar::new(len).with_message(format!("{msg} {:?}", self));
-        progress.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.cyan/blue} {bytes:>7}/{total_bytes:7} [{bytes_per_sec}] {msg}\n")
-            .unwrap()
-            .progress_chars("->#"),
-    );
-        self.progress_bar = Some(progress);
+        let progress = ProgressBar::new(len)
+            .with_message(format!("{msg} {:?}", self))
+            .with_style(progress_style());
+        self.progress_bar = Some(self.show_progress(progress));
         self
     }
 
@@ -457,12 +1207,28 @@ impl FileFacade {
         self.comparaison.as_ref().unwrap().clone()
     }
 
+    /// Hash of the working file, taken from the logbook when the file hasn't been touched
+    /// since a version was recorded from it
+    pub async fn working_object(&mut self, progress: &ProgressBar) -> String {
+        let original = self.file.original_path();
+        self.file.set_metadata(&original.metadata().unwrap());
+        if self.logbook.db.is_some() {
+            if let Some(object) = self.logbook.cached_object(&self.file).await {
+                progress.inc(self.file.size);
+                return object;
+            }
+        }
+        hash_file(&original, progress).await
+    }
+
     pub async fn add(mut self) -> Self {
         let original = self.file.original_path();
-        let duplicata = self.file.history_path();
+        let hashing =
+            self.show_progress(hashing_progress(original.metadata().unwrap().size()));
+        let object = self.working_object(&hashing).await;
+        hashing.finish_and_clear();
         self.set_progress_bar(original.metadata().unwrap().size(), "Copying");
-        self.duplicate(&original, &duplicata).await;
-        self.logbook.create().await;
+        self.save_object(&object).await;
         self.logbook.insert(&self.file).await;
         self.progress_bar.as_ref().unwrap().finish();
         self
@@ -472,115 +1238,383 @@ impl FileFacade {
         self.changed
     }
 
-    pub async fn compare(&self, msg: &str) -> &Self {
-        //TODO: if commiting we see that the file hasn't changed we may want to
-        //use a symlink
-        let previous = self.previous_version();
-        let diff = self.comparaison().compare(self, &previous).result();
-        self.logbook.insert(&diff).await;
+    /// Store the working file and compare it with the previous version. Nothing is written
+    /// in the logbook until the commit is recorded.
+    pub async fn prepare(mut self, msg: &str) -> Result<Self, String> {
+        let previous = match self.previous_version().await {
+            Some(v) => v,
+            None => {
+                println!("{:?} is not tracked yet, add it first", self.path());
+                self.changed = false;
+                return Ok(self);
+            },
+        };
+        let size = self
+            .file
+            .original_path()
+            .metadata()
+            .map_err(|err| format!("{:?}: {err}", self.path()))?
+            .size();
+        let hashing = self.show_progress(hashing_progress(size));
+        let object = self.working_object(&hashing).await;
+        hashing.finish_and_clear();
+        // Same content means same object, nothing to compare nor to store
+        self.changed = previous.file.object() != object;
+        if !self.changed {
+            return Ok(self);
+        }
+        // Compare against the stored object so the result matches what is recorded and the
+        // current version already knows its hash
+        self.set_progress_bar(size, "Copying");
+        self.save_object(&object).await;
+        self.progress_bar.as_ref().unwrap().finish();
 
-        let original = self.file.original_path();
-        let duplicata = self.file.history_path();
-        self.duplicate(&original, &duplicata).await;
+        fetch_object(&self, &previous.file).await?;
+        let diff = self.comparaison().compare(&self, &previous).await.result();
+        self.release_object(&previous.file).await;
 
-        let git_commit = get_latest_git_commit().await;
         let commit = Commit::new(
             self.file.branch.clone(),
-            self.file.clone(),
             previous.file,
+            self.file.clone(),
             msg.to_owned(),
             self.file.author.clone(),
         )
         .set_diff(&diff)
-        .set_git_commit(git_commit);
-        self.logbook.insert(&commit).await;
-        self
+        .set_git_commit(get_latest_git_commit().await);
+        self.commit = Some(commit);
+        Ok(self)
     }
 
-    pub async fn remove(self) -> Self {
-        let remote = self.remote();
-        let operator = remote.get_storage_operator();
-        operator
-            .delete(self.path().to_str().unwrap())
-            .await
-            .unwrap();
-        self
+    /// Write the prepared diff, version and commit under the repository commit
+    pub async fn record(&mut self, root_commit: u32) -> Result<(), String> {
+        let commit = self.commit.take().expect("the file has not been prepared");
+        let diff = commit.diff().expect("prepared commits have a diff").clone();
+        let pk = self.logbook.try_save(&diff).await?;
+        self.recorded.push(("diffs", pk));
+        let diff = diff.set_pk(pk);
+        let pk = self.logbook.try_save(&self.file).await?;
+        self.recorded.push(("files", pk));
+        let commit = commit.set_diff(&diff).set_root_commit(root_commit);
+        let pk = self.logbook.try_save(&commit).await?;
+        self.recorded.push(("commits", pk));
+        Ok(())
+    }
+
+    /// Delete the rows recorded for a commit that could not be completed. The stored object
+    /// is left to the garbage collection.
+    pub async fn rollback(&mut self) {
+        for (table, id) in self.recorded.drain(..).rev() {
+            self.logbook.delete_row(table, id).await;
+        }
     }
 
-    pub async fn push(mut self) -> Self {
-        let remote = push_file(&self).await;
+    pub async fn remove(self) -> Result<Self, String> {
+        remove_file(&self).await?;
+        Ok(self)
+    }
+
+    pub async fn push(mut self) -> Result<Self, String> {
+        let (remote, pushed) = push_file(&self).await?;
         self.logbook.insert(&remote).await;
+        for version in &pushed {
+            self.logbook.mark_pushed(version, &remote.storage).await;
+        }
         self.file.set_remote(remote);
-        self.logbook.insert(&self.file).await;
         //TODO: push the comparaison results
         //TODO: it would be cool to save the errors if any from the copies and so on and save them
         // in the db
-        self
+        Ok(self)
     }
 
-    pub async fn pull(mut self) -> Self {
-        let remote = pull_file(&self).await;
+    /// Pull the given object, or the latest one pushed for the branch
+    pub async fn pull(mut self, object: Option<String>) -> Result<Self, String> {
+        let (remote, version) = pull_file(&self, object).await?;
         self.logbook.insert(&remote).await;
+        self.file = version;
         self.file.set_remote(remote);
+        if !self.logbook.has_version(&self.file).await {
+            self.logbook.insert(&self.file).await;
+            self.logbook
+                .mark_pushed(&self.file, &self.remote().storage)
+                .await;
+        }
+        Ok(self)
+    }
+
+    pub async fn versions(&self) -> Vec<File> {
+        self.logbook.versions(&self.file).await
+    }
+
+    /// Bring the latest version of the file in the source branch into the facade branch
+    pub async fn merge(mut self, source: &str, msg: &str) -> Self {
+        let mut source_file = self.file.clone();
+        source_file.branch = source.to_owned();
+        let incoming = self.logbook.latest_version(&source_file).await;
+        let current = self.logbook.latest_version(&self.file).await;
+        let incoming = match incoming {
+            Some(v) if current.as_ref().map(File::object) != Some(v.object()) => v,
+            _ => {
+                self.changed = false;
+                return self;
+            },
+        };
+        self.file.object = incoming.object;
         self.logbook.insert(&self.file).await;
+        let commit = Commit::new(
+            self.file.branch.clone(),
+            current.unwrap_or_default(),
+            self.file.clone(),
+            msg.to_owned(),
+            self.file.author.clone(),
+        )
+        .set_git_commit(get_latest_git_commit().await);
+        self.logbook.insert(&commit).await;
         self
     }
 
+    pub fn has_logbook(&self) -> bool {
+        self.logbook.exists()
+    }
+
+    pub async fn log(&self, filter: &LogFilter) -> Vec<LogEntry> {
+        self.logbook.log(&self.file, filter).await
+    }
+
+    /// Compare the working file with the latest version of its branch
+    pub async fn status(&mut self, progress: &ProgressBar) -> StatusEntry {
+        let original = self.original_path();
+        let latest = match self.has_logbook() {
+            true => self.logbook.latest_version(&self.file).await,
+            false => None,
+        };
+        let status = match latest {
+            _ if !original.exists() => FileStatus::Missing,
+            None => FileStatus::Untracked,
+            Some(version) if self.working_object(progress).await == version.object() => {
+                FileStatus::Unchanged
+            },
+            Some(_) => FileStatus::Modified,
+        };
+        let unpushed = match self.has_logbook() {
+            true => self.logbook.unpushed(&self.file).await,
+            false => 0,
+        };
+        StatusEntry {
+            path: self.path().to_path_buf(),
+            status,
+            unpushed,
+        }
+    }
+
+    /// Build the facade of the given version, fetching it from the remote if needed
+    /// Version recorded in the logbook, telling apart the ones garbage collected
+    async fn find_version(&self, version: &VersionRef) -> Result<File, String> {
+        if let Some(found) = self.logbook.find_version(&self.file, version).await {
+            return Ok(found);
+        }
+        match self.logbook.collected(&self.file, version).await {
+            true => Err(format!(
+                "The version {:?} of {:?} was garbage collected",
+                version,
+                self.path()
+            )),
+            false => Err(format!("No version {:?} for {:?}", version, self.path())),
+        }
+    }
+
+    pub async fn resolve(&self, version: &VersionRef) -> Result<FileFacade, String> {
+        if version == &VersionRef::Working {
+            return Ok(FileFacade::new(self.file()));
+        }
+        let version = self.find_version(version).await?;
+        fetch_object(self, &version).await?;
+        Ok(FileFacade::new(version))
+    }
+
+    pub async fn stored_diff(
+        &self,
+        from: &FileFacade,
+        to: &FileFacade,
+    ) -> Option<(String, Value)> {
+        self.logbook.stored_diff(&from.file, &to.file).await
+    }
+
+    /// Run the comparaison between two versions without saving anything
+    pub async fn diff(&self, from: &FileFacade, to: &FileFacade) -> Diff {
+        self.comparaison().compare(to, from).await.result()
+    }
+
+    /// Override the working file with a recorded version, fetching it from the remote when
+    /// it is not in the local history anymore
+    pub async fn checkout(self, version: &VersionRef) -> Result<Self, String> {
+        let version = self.find_version(version).await?;
+        fetch_object(&self, &version).await?;
+        let original = self.original_path();
+        tokio::fs::create_dir_all(original.parent().unwrap())
+            .await
+            .unwrap();
+        tokio::fs::copy(version.history_path(), &original)
+            .await
+            .unwrap_or_else(|err| panic!("{}: unable to restore {:?}", err, original));
+        self.release_object(&version).await;
+        println!("{:?} restored to {}", self.path(), version.object());
+        Ok(self)
+    }
+
     // Utils
-    pub fn previous_version(&self) -> FileFacade {
-        //TODO: for now just look in the history dir, later on check the dabbatase and find the
-        //previous file even if remote
-        //TODO: check if we want to keep this list in memory
-        let mut files = self
-            .file
-            .history_dir()
-            .read_dir()
+    pub async fn previous_version(&self) -> Option<FileFacade> {
+        self.logbook
+            .latest_version(&self.file)
+            .await
+            .map(FileFacade::new)
+    }
+
+    /// Path where the content of this version can be read. Versions already saved in the
+    /// history are read from the object store, otherwise it is the working file.
+    /// Chunked objects are only assembled when needed, so a version just made from the
+    /// working file is read from it.
+    pub fn content_path(&self) -> PathBuf {
+        match &self.file.object {
+            Some(o) if self.file.modified != 0 && !self.file.store().contains(o) => {
+                self.file.original_path()
+            },
+            Some(_) => self.file.history_path(),
+            None => self.file.original_path(),
+        }
+    }
+
+    pub async fn all_versions(&self) -> Vec<File> {
+        self.logbook.all_versions(&self.file).await
+    }
+
+    pub async fn git_objects(&self) -> HashSet<String> {
+        self.logbook.git_objects().await
+    }
+
+    pub async fn forget(&self, objects: &HashSet<String>) {
+        self.logbook.forget(objects).await
+    }
+
+    pub async fn manifest(&self, version: &File) -> Vec<Chunk> {
+        self.logbook.manifest(&version.object()).await
+    }
+
+    pub async fn save_manifest(&self, object: &str, chunks: &[Chunk]) {
+        self.logbook.save_manifest(object, chunks).await
+    }
+
+    /// Drop the assembled object of a chunked version once it has been read
+    pub async fn release_object(&self, version: &File) {
+        if !self.manifest(version).await.is_empty() {
+            version.store().evict(&version.object()).await;
+        }
+    }
+
+    fn is_chunked(&self) -> bool {
+        match (
+            self.chunking_threshold,
+            self.file.original_path().metadata(),
+        ) {
+            (Some(threshold), Ok(metadata)) => metadata.len() > threshold,
+            _ => false,
+        }
+    }
+
+    async fn save_object(&mut self, object: &str) -> &Self {
+        let store = self.file.store();
+        let progress = self.progress_bar.as_ref().unwrap();
+        if !self.is_chunked() {
+            store
+                .save(&self.file.original_path(), object, progress)
+                .await;
+        } else if self.logbook.manifest(object).await.is_empty() {
+            let chunks = store
+                .save_chunks(&self.file.original_path(), progress)
+                .await;
+            self.logbook.save_manifest(object, &chunks).await;
+        } else {
+            progress.inc(self.file.size);
+        }
+        self.file.object = Some(object.to_owned());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcs::comparaison::ComparaisonTechnique;
+
+    async fn count(conn: &Connection, table: &str) -> u32 {
+        conn.query(&format!("SELECT COUNT(*) FROM {table}"), ())
+            .await
             .unwrap()
-            .map(|f| {
-                f.unwrap()
-                    .file_name()
-                    .into_string()
-                    .unwrap()
-                    .parse::<i64>()
-                    .unwrap()
-            })
-            .collect::<Vec<i64>>();
-        files.sort();
-        //TODO: poping the last elemnt will do the trick for now. find a better way to find the
-        //previous
-        let prev_timestamp = files.pop().unwrap();
+            .next()
+            .await
+            .unwrap()
+            .unwrap()
+            .get::<u32>(0)
+            .unwrap()
+    }
+
+    /// Tracked file whose new content is prepared for a commit
+    async fn prepared_file(dir: &Path, name: &str) -> FileFacade {
+        let path = dir.join(name);
+        tokio::fs::write(&path, "first\n").await.unwrap();
         let file = File::new(
-            &self.file.path,
-            &self.file.branch,
-            &self.file.history,
-            prev_timestamp,
-            self.file.author.clone(),
+            &path,
+            "main",
+            dir.join("history").to_str().unwrap(),
+            0,
+            Author::default(),
         );
-        FileFacade::new(file)
+        let facade = FileFacade::new(file)
+            .set_logbook(FileLogbook::new(&path, &dir.join("logbooks")))
+            .set_comparaison(&Comparaison::new(&ComparaisonTechnique::Hash, &None))
+            .init()
+            .await
+            .add()
+            .await;
+        tokio::fs::write(&path, "first\nsecond\n").await.unwrap();
+        facade.prepare("second line").await.unwrap()
     }
 
-    pub async fn duplicate(&self, original: &Path, duplicata: &Path) -> &Self {
-        tokio::fs::create_dir_all(
-            duplicata
-                .parent()
-                .expect("unable to get duplciate local path parent"),
-        )
-        .await
-        .expect("Couldn't create dirs");
-        let mut origin_file = tokio::fs::File::open(&original).await.unwrap();
-        let duplicated_file = tokio::fs::File::create(&duplicata).await.unwrap();
-        match tokio::io::copy(
-            &mut origin_file,
-            &mut self
-                .progress_bar
-                .as_ref()
-                .unwrap()
-                .wrap_async_write(duplicated_file),
-        )
-        .await
-        {
-            Ok(_) => self,
-            Err(err) => panic!("{}: {:?}", err, &original),
+    #[tokio::test]
+    async fn test_failed_commit_is_rolled_back() {
+        let dir =
+            std::env::temp_dir().join(format!("yap-rollback-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let root_logbook = Logbook::local(dir.join("logbook.db").to_str().unwrap()).await;
+        let mut files = vec![
+            prepared_file(&dir, "a.csv").await,
+            prepared_file(&dir, "b.csv").await,
+        ];
+        // The second file records its diff and its version but not its commit
+        files[1]
+            .logbook
+            .conn()
+            .await
+            .execute("DROP TABLE commits", ())
+            .await
+            .unwrap();
+
+        let id = root_logbook
+            .begin_commit(0, "second line", "main", &Author::default(), "")
+            .await
+            .unwrap();
+        assert!(root_logbook.record_commit(id, &mut files).await.is_err());
+
+        let root = root_logbook.conn();
+        assert_eq!(count(&root, "commits").await, 0);
+        assert_eq!(count(&root, "commit_files").await, 0);
+        for file in &files {
+            let conn = file.logbook.conn().await;
+            assert_eq!(count(&conn, "diffs").await, 0);
+            // Only the version added before the commit is left
+            assert_eq!(count(&conn, "files").await, 1);
         }
+        assert_eq!(count(&files[0].logbook.conn().await, "commits").await, 0);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
     }
 }
@@ -0,0 +1,323 @@
+use super::{
+    cli::{current_branch, parse_duration, Events},
+    file::{File, FileFacadeFactory, Logbook},
+    objects::ObjectStore,
+    remote::collect_garbage,
+};
+use crate::config::Config;
+
+use clap::{ArgAction, ArgGroup, Args};
+use indicatif::HumanBytes;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    env,
+    path::PathBuf,
+};
+
+/// Remove the versions no retention policy keeps. The latest version of every branch is
+/// always kept.
+#[derive(Debug, Args, Clone)]
+#[command(group(ArgGroup::new("retention").required(true).multiple(true)))]
+pub struct Gc {
+    // Keep the N latest versions of every branch
+    #[arg(long, group = "retention")]
+    keep_last: Option<usize>,
+
+    // Keep the versions newer than a duration like 90s, 30m, 12h, 7d or 4w
+    #[arg(long, group = "retention", value_parser = parse_duration)]
+    keep_within: Option<i64>,
+
+    // Keep the versions having a tag
+    #[arg(long, group = "retention", action = ArgAction::SetTrue)]
+    keep_tagged: bool,
+
+    // Keep the versions commited along a git commit
+    #[arg(long, group = "retention", action = ArgAction::SetTrue)]
+    keep_git: bool,
+
+    // Also delete the objects from the remote
+    #[arg(long, action = ArgAction::SetTrue)]
+    remote: bool,
+
+    // Only report what would be removed
+    #[arg(long, action = ArgAction::SetTrue)]
+    dry_run: bool,
+}
+
+impl Gc {
+    pub async fn run(&self, config: &Config) -> i16 {
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        let paths: BTreeSet<PathBuf> = root_logbook
+            .files_tracked()
+            .await
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        let files = FileFacadeFactory::new(
+            paths.into_iter().collect(),
+            &current_branch(config).await,
+            config,
+        );
+        let now = chrono::offset::Local::now().timestamp();
+        let verb = if self.dry_run { "would be" } else { "were" };
+
+        // Objects and chunks still referenced once the collection is done
+        let mut kept = HashSet::new();
+        let mut forgotten = HashSet::new();
+        for file in files {
+            if !file.has_logbook() {
+                continue;
+            }
+            let file = file.init().await;
+            let versions = file.all_versions().await;
+            let tagged = match self.keep_tagged {
+                true => root_logbook.tagged_objects(file.path()).await,
+                false => HashSet::new(),
+            };
+            let git = match self.keep_git {
+                true => file.git_objects().await,
+                false => HashSet::new(),
+            };
+            let retained = self.retained(&versions, &tagged, &git, now);
+            let removed: HashSet<String> = versions
+                .iter()
+                .map(File::object)
+                .filter(|o| !retained.contains(o))
+                .collect();
+            for object in &retained {
+                kept.insert(object.clone());
+                for chunk in file.manifest(&file.file().set_object(object)).await {
+                    kept.insert(chunk.id);
+                }
+            }
+            for object in &removed {
+                for chunk in file.manifest(&file.file().set_object(object)).await {
+                    forgotten.insert(chunk.id);
+                }
+            }
+            if removed.is_empty() {
+                continue;
+            }
+            println!(
+                "{:?}: {} versions {verb} removed",
+                file.path(),
+                removed.len()
+            );
+            if !self.dry_run {
+                file.forget(&removed).await;
+                root_logbook.save_event(&file, &Events::Gc).await;
+            }
+            forgotten.extend(removed);
+        }
+
+        let store =
+            ObjectStore::new(&env::current_dir().unwrap().join(config.history_dir()));
+        let garbage: Vec<(String, u64)> = store
+            .entries()
+            .into_iter()
+            .filter(|(object, _)| !kept.contains(object))
+            .collect();
+        let local_bytes: u64 = garbage.iter().map(|(_, size)| size).sum();
+        if !self.dry_run {
+            for (object, _) in &garbage {
+                store.remove(object).await;
+            }
+        }
+        println!(
+            "{} objects {verb} removed from the history, {} reclaimed",
+            garbage.len(),
+            HumanBytes(local_bytes)
+        );
+
+        if self.remote {
+            let operator = config.remote_storage().get_storage_operator();
+            let garbage: HashSet<String> = forgotten.difference(&kept).cloned().collect();
+            let remote_bytes = collect_garbage(&operator, &garbage, self.dry_run).await;
+            println!(
+                "{} {verb} reclaimed from the remote",
+                HumanBytes(remote_bytes)
+            );
+        }
+        0
+    }
+
+    /// Objects kept by the policies, versions are ordered from the newest
+    fn retained(
+        &self,
+        versions: &[File],
+        tagged: &HashSet<String>,
+        git: &HashSet<String>,
+        now: i64,
+    ) -> HashSet<String> {
+        let mut ranks: HashMap<&str, usize> = HashMap::new();
+        versions
+            .iter()
+            .filter(|version| {
+                let rank = ranks.entry(version.branch()).or_default();
+                let object = version.object();
+                let keep = *rank == 0
+                    || self.keep_last.is_some_and(|n| *rank < n)
+                    || self
+                        .keep_within
+                        .is_some_and(|within| version.timestamp() >= now - within)
+                    || tagged.contains(&object)
+                    || git.contains(&object);
+                *rank += 1;
+                keep
+            })
+            .map(File::object)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Author,
+        vcs::{file::FileFacade, testing::Repository, versioning::VersionRef},
+    };
+    use std::path::Path;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn gc() -> Gc {
+        Gc {
+            keep_last: None,
+            keep_within: None,
+            keep_tagged: false,
+            keep_git: false,
+            remote: false,
+            dry_run: false,
+        }
+    }
+
+    /// Objects kept among versions named after their branch and their rank in it
+    fn retained(gc: &Gc, tagged: &[&str], git: &[&str]) -> BTreeSet<String> {
+        let versions: Vec<File> = [
+            ("main-0", 10),
+            ("dev-0", 9),
+            ("main-1", 8),
+            ("main-2", 5),
+            ("dev-1", 2),
+        ]
+        .into_iter()
+        .map(|(object, day)| {
+            let branch = object.split('-').next().unwrap();
+            File::new(
+                Path::new("data.csv"),
+                branch,
+                "",
+                day * DAY,
+                Author::default(),
+            )
+            .set_object(object)
+        })
+        .collect();
+        let set = |objects: &[&str]| -> HashSet<String> {
+            objects.iter().map(|o| o.to_string()).collect()
+        };
+        gc.retained(&versions, &set(tagged), &set(git), 10 * DAY)
+            .into_iter()
+            .collect()
+    }
+
+    fn objects(objects: &[&str]) -> BTreeSet<String> {
+        objects.iter().map(|o| o.to_string()).collect()
+    }
+
+    #[test]
+    fn test_retained_by_policy() {
+        // The latest version of each branch is always kept
+        assert_eq!(retained(&gc(), &[], &[]), objects(&["main-0", "dev-0"]));
+
+        let keep_last = Gc {
+            keep_last: Some(2),
+            ..gc()
+        };
+        assert_eq!(
+            retained(&keep_last, &[], &[]),
+            objects(&["main-0", "main-1", "dev-0", "dev-1"])
+        );
+
+        let keep_within = Gc {
+            keep_within: Some(3 * DAY),
+            ..gc()
+        };
+        assert_eq!(
+            retained(&keep_within, &[], &[]),
+            objects(&["main-0", "main-1", "dev-0"])
+        );
+
+        let keep_tagged = Gc {
+            keep_tagged: true,
+            ..gc()
+        };
+        assert_eq!(
+            retained(&keep_tagged, &["main-2"], &[]),
+            objects(&["main-0", "main-2", "dev-0"])
+        );
+
+        let keep_git = Gc {
+            keep_git: true,
+            ..gc()
+        };
+        assert_eq!(
+            retained(&keep_git, &[], &["dev-1"]),
+            objects(&["main-0", "dev-0", "dev-1"])
+        );
+    }
+    async fn tracked(config: &Config) -> FileFacade {
+        FileFacadeFactory::new(vec![PathBuf::from("data.txt")], "main", config)
+            .next()
+            .unwrap()
+            .init()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_gc_repository() {
+        let repository = Repository::new("gc").await;
+        for (i, content) in ["first\n", "second\n", "third\n"].iter().enumerate() {
+            repository.write("data.txt", content).await;
+            let args: &[&str] = match i {
+                0 => &["add", "-p", "data.txt"],
+                _ => &["commit", "-p", "data.txt", "-m", content.trim()],
+            };
+            assert_eq!(repository.run(args).await, 0);
+        }
+        let config = repository.config();
+        let store = ObjectStore::new(&repository.dir.join(config.history_dir()));
+        assert_eq!(store.entries().len(), 3);
+
+        assert_eq!(
+            repository
+                .run(&["gc", "--keep-last", "1", "--dry-run"])
+                .await,
+            0
+        );
+        assert_eq!(store.entries().len(), 3);
+        assert_eq!(tracked(&config).await.all_versions().await.len(), 3);
+        assert!(tracked(&config)
+            .await
+            .resolve(&VersionRef::Commit(1))
+            .await
+            .is_ok());
+
+        assert_eq!(repository.run(&["gc", "--keep-last", "1"]).await, 0);
+        assert_eq!(store.entries().len(), 1);
+        assert_eq!(tracked(&config).await.all_versions().await.len(), 1);
+        let err = tracked(&config)
+            .await
+            .checkout(&VersionRef::Commit(1))
+            .await
+            .unwrap_err();
+        assert!(err.contains("garbage collected"), "{err}");
+        assert!(tracked(&config)
+            .await
+            .resolve(&VersionRef::Commit(2))
+            .await
+            .is_ok());
+        assert_eq!(repository.read("data.txt").await, "third\n");
+    }
+}
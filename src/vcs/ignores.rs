@@ -0,0 +1,142 @@
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+pub const IGNORE_FILE: &str = ".yapignore";
+
+// Never worth versioning, whatever the ignore files say
+const DEFAULT_PATTERNS: [&str; 2] = [".git/", ".yap/"];
+
+/// Gitignore-style rules read from the `.yapignore` files of every directory plus the
+/// global patterns of the config. Deeper files take precedence over the ones above them.
+#[derive(Debug, Default)]
+pub struct IgnoreRules {
+    root: PathBuf,
+    global: Option<Gitignore>,
+    // Rules of every directory visited, None when it has no ignore file
+    dirs: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl IgnoreRules {
+    pub fn new(root: &Path, patterns: &[String]) -> Self {
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in DEFAULT_PATTERNS
+            .iter()
+            .copied()
+            .chain(patterns.iter().map(String::as_str))
+        {
+            builder
+                .add_line(None, pattern)
+                .unwrap_or_else(|err| panic!("invalid ignore pattern {pattern}: {err}"));
+        }
+        Self {
+            root: root.to_path_buf(),
+            global: Some(
+                builder
+                    .build()
+                    .expect("unable to build the ignore patterns"),
+            ),
+            dirs: HashMap::new(),
+        }
+    }
+
+    pub fn is_ignored(&mut self, path: &Path) -> bool {
+        let path = self.root.join(path);
+        // Outside of the repository nothing applies
+        if !path.starts_with(&self.root) {
+            return false;
+        }
+        let is_dir = path.is_dir();
+        let dirs: Vec<PathBuf> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.root))
+            .map(Path::to_path_buf)
+            .collect();
+        for dir in dirs {
+            let rules = self
+                .dirs
+                .entry(dir.clone())
+                .or_insert_with(|| Self::read_dir_rules(&dir));
+            match rules
+                .as_ref()
+                .map(|r| r.matched_path_or_any_parents(&path, is_dir))
+            {
+                Some(Match::Ignore(_)) => return true,
+                Some(Match::Whitelist(_)) => return false,
+                _ => {},
+            }
+        }
+        self.global
+            .as_ref()
+            .is_some_and(|g| g.matched_path_or_any_parents(&path, is_dir).is_ignore())
+    }
+
+    /// Files under the directory that are not ignored
+    pub fn walk(&mut self, dir: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let mut stack = vec![dir.to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            let Ok(entries) = dir.read_dir() else {
+                continue;
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                let path = path
+                    .strip_prefix("./")
+                    .map(Path::to_path_buf)
+                    .unwrap_or(path);
+                if self.is_ignored(&path) {
+                    continue;
+                }
+                match path.is_dir() {
+                    true => stack.push(path),
+                    false => files.push(path),
+                }
+            }
+        }
+        files
+    }
+
+    fn read_dir_rules(dir: &Path) -> Option<Gitignore> {
+        let file = dir.join(IGNORE_FILE);
+        if !file.exists() {
+            return None;
+        }
+        let (rules, err) = Gitignore::new(&file);
+        if let Some(err) = err {
+            println!("{:?}: {err}", file);
+        }
+        Some(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_rules() {
+        let dir = std::env::temp_dir().join(format!("yap-ignore-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("data/tmp")).unwrap();
+        std::fs::write(dir.join(IGNORE_FILE), "*.log\ndata/tmp/\n").unwrap();
+        std::fs::write(dir.join("data").join(IGNORE_FILE), "!keep.log\n").unwrap();
+
+        let mut rules = IgnoreRules::new(&dir, &[".DS_Store".to_string()]);
+        assert!(rules.is_ignored(Path::new("run.log")));
+        assert!(!rules.is_ignored(Path::new("data/keep.log")));
+        assert!(rules.is_ignored(Path::new("data/other.log")));
+        assert!(rules.is_ignored(Path::new("data/tmp")));
+        assert!(rules.is_ignored(Path::new("data/tmp/a.csv")));
+        assert!(rules.is_ignored(Path::new("data/.DS_Store")));
+        assert!(rules.is_ignored(Path::new(".yap")));
+        assert!(!rules.is_ignored(Path::new("data/a.csv")));
+        assert!(!rules.is_ignored(Path::new("/elsewhere/run.log")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
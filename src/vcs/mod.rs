@@ -1,7 +1,15 @@
+mod branch;
 pub mod cli;
 mod comparaison;
 mod file;
+mod gc;
+mod ignores;
+mod objects;
+mod pathspec;
 mod remote;
+mod tag;
+#[cfg(test)]
+mod testing;
 mod versioning;
 
 pub use cli::VcsArgs;
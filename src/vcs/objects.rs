@@ -0,0 +1,279 @@
+use digest::Digest;
+use fastcdc::v2020::StreamCDC;
+use indicatif::{ProgressBar, ProgressStyle};
+use meowhash::MeowHasher;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const HASH_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+// Bounds given to the content defined chunker
+const CHUNK_MIN_SIZE: u32 = 512 * 1024;
+const CHUNK_AVG_SIZE: u32 = 2 * 1024 * 1024;
+const CHUNK_MAX_SIZE: u32 = 8 * 1024 * 1024;
+
+/// Piece of a big file stored as its own object. The ordered list of chunks of a version is
+/// its manifest.
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+pub struct Chunk {
+    pub id: String,
+    pub size: u64,
+}
+
+/// Content addressed storage living inside the history dir. Every version is saved under
+/// the hash of its content, so identical versions across commits and branches are only
+/// stored once.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectStore {
+    root: PathBuf,
+}
+
+impl ObjectStore {
+    pub fn new(history_dir: &Path) -> Self {
+        Self {
+            root: history_dir.join("objects"),
+        }
+    }
+
+    pub fn object_path(&self, object: &str) -> PathBuf {
+        // Split like git does so a single directory doesn't end up with millions of entries
+        let (prefix, rest) = object.split_at(2);
+        self.root.join(prefix).join(rest)
+    }
+
+    pub fn contains(&self, object: &str) -> bool {
+        self.object_path(object).exists()
+    }
+
+    pub async fn save(&self, original: &Path, object: &str, progress: &ProgressBar) {
+        let duplicata = self.object_path(object);
+        if duplicata.exists() {
+            progress.inc(original.metadata().map(|m| m.len()).unwrap_or_default());
+            return;
+        }
+        tokio::fs::create_dir_all(
+            duplicata
+                .parent()
+                .expect("unable to get the object parent dir"),
+        )
+        .await
+        .expect("Couldn't create dirs");
+        // Write first to a temporary file so an interrupted copy never looks like a valid
+        // object
+        let tmp = duplicata.with_extension("tmp");
+        let mut origin_file = tokio::fs::File::open(&original).await.unwrap();
+        let duplicated_file = tokio::fs::File::create(&tmp).await.unwrap();
+        if let Err(err) = tokio::io::copy(
+            &mut origin_file,
+            &mut progress.wrap_async_write(duplicated_file),
+        )
+        .await
+        {
+            panic!("{}: {:?}", err, &original)
+        };
+        tokio::fs::rename(&tmp, &duplicata)
+            .await
+            .expect("unable to move the object into the store");
+    }
+
+    /// Split the file with a content defined chunker and store the chunks not in the store
+    /// yet. Appending or editing a part of the file only changes the chunks around the edit.
+    pub async fn save_chunks(
+        &self,
+        original: &Path,
+        progress: &ProgressBar,
+    ) -> Vec<Chunk> {
+        let store = self.clone();
+        let original = original.to_path_buf();
+        let progress = progress.clone();
+        tokio::task::spawn_blocking(move || {
+            let source = std::fs::File::open(&original)
+                .unwrap_or_else(|err| panic!("{}: {:?}", err, &original));
+            StreamCDC::new(source, CHUNK_MIN_SIZE, CHUNK_AVG_SIZE, CHUNK_MAX_SIZE)
+                .map(|chunk| {
+                    let chunk =
+                        chunk.unwrap_or_else(|err| panic!("{}: {:?}", err, &original));
+                    let id = hash_bytes(&chunk.data);
+                    let path = store.object_path(&id);
+                    if !path.exists() {
+                        std::fs::create_dir_all(path.parent().unwrap())
+                            .expect("Couldn't create dirs");
+                        let tmp = path.with_extension("tmp");
+                        std::fs::write(&tmp, &chunk.data)
+                            .unwrap_or_else(|err| panic!("{}: {:?}", err, &tmp));
+                        std::fs::rename(&tmp, &path)
+                            .expect("unable to move the chunk into the store");
+                    }
+                    progress.inc(chunk.length as u64);
+                    Chunk {
+                        id,
+                        size: chunk.length as u64,
+                    }
+                })
+                .collect()
+        })
+        .await
+        .expect("unable to chunk the file")
+    }
+
+    pub fn has_chunks(&self, chunks: &[Chunk]) -> bool {
+        chunks.iter().all(|c| self.contains(&c.id))
+    }
+
+    /// Rebuild the whole object from its chunks
+    pub async fn assemble(&self, object: &str, chunks: &[Chunk]) {
+        let duplicata = self.object_path(object);
+        tokio::fs::create_dir_all(
+            duplicata
+                .parent()
+                .expect("unable to get the object parent dir"),
+        )
+        .await
+        .expect("Couldn't create dirs");
+        let tmp = duplicata.with_extension("tmp");
+        let mut assembled = tokio::fs::File::create(&tmp).await.unwrap();
+        for chunk in chunks {
+            let data = tokio::fs::read(self.object_path(&chunk.id))
+                .await
+                .unwrap_or_else(|err| panic!("missing chunk {}: {err}", chunk.id));
+            assembled.write_all(&data).await.unwrap();
+        }
+        assembled.flush().await.unwrap();
+        tokio::fs::rename(&tmp, &duplicata)
+            .await
+            .expect("unable to move the object into the store");
+    }
+
+    /// Every object and chunk in the store with its size
+    pub fn entries(&self) -> Vec<(String, u64)> {
+        let prefixes = match self.root.read_dir() {
+            Ok(prefixes) => prefixes,
+            Err(_) => return Vec::new(),
+        };
+        prefixes
+            .filter_map(|prefix| prefix.ok())
+            .filter_map(|prefix| {
+                let name = prefix.file_name().to_string_lossy().to_string();
+                prefix.path().read_dir().ok().map(|entries| (name, entries))
+            })
+            .flat_map(|(prefix, entries)| {
+                entries.filter_map(|e| e.ok()).map(move |entry| {
+                    let size = entry.metadata().map(|m| m.len()).unwrap_or_default();
+                    (
+                        format!("{prefix}{}", entry.file_name().to_string_lossy()),
+                        size,
+                    )
+                })
+            })
+            .collect()
+    }
+
+    pub async fn remove(&self, object: &str) {
+        tokio::fs::remove_file(self.object_path(object))
+            .await
+            .unwrap_or_else(|err| panic!("unable to remove {object}: {err}"));
+    }
+
+    /// Drop an assembled object, its chunks are still in the store
+    pub async fn evict(&self, object: &str) {
+        let _ = tokio::fs::remove_file(self.object_path(object)).await;
+    }
+}
+
+pub fn hash_bytes(data: &[u8]) -> String {
+    hex::encode(MeowHasher::digest(data))
+}
+
+/// Hash the file chunk by chunk so it never has to fit in memory. The hashing itself runs on
+/// the blocking pool, letting several files be hashed in parallel.
+pub async fn hash_file(path: &Path, progress: &ProgressBar) -> String {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .unwrap_or_else(|err| panic!("{}: {:?}", err, path));
+    let mut hasher = MeowHasher::new();
+    loop {
+        let mut chunk = vec![0; HASH_CHUNK_SIZE];
+        let bytes_read = file
+            .read(&mut chunk)
+            .await
+            .unwrap_or_else(|err| panic!("{}: {:?}", err, path));
+        if bytes_read == 0 {
+            break;
+        }
+        chunk.truncate(bytes_read);
+        hasher = tokio::task::spawn_blocking(move || {
+            hasher.update(&chunk);
+            hasher
+        })
+        .await
+        .expect("unable to hash the chunk");
+        progress.inc(bytes_read as u64);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Number of files hashed at the same time
+pub fn hash_concurrency() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+}
+
+/// Style of the progress bars counting the bytes hashed, copied or transferred
+pub fn progress_style() -> ProgressStyle {
+    ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.cyan/blue} {bytes:>7}/{total_bytes:7} [{bytes_per_sec}] {msg}\n")
+        .unwrap()
+        .progress_chars("#->")
+}
+
+pub fn hashing_progress(len: u64) -> ProgressBar {
+    ProgressBar::new(len)
+        .with_message("Hashing")
+        .with_style(progress_style())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_hash_file_matches_hash_bytes() {
+        // Bigger than a chunk so the hash is built from several updates
+        let data: Vec<u8> = (0..HASH_CHUNK_SIZE + 1000)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let path = std::env::temp_dir()
+            .join(format!("yap-hash-file-{}.bin", std::process::id()));
+        tokio::fs::write(&path, &data).await.unwrap();
+        let progress = ProgressBar::hidden();
+        assert_eq!(hash_file(&path, &progress).await, hash_bytes(&data));
+        assert_eq!(progress.position(), data.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_chunks_are_shared_between_versions() {
+        let root =
+            std::env::temp_dir().join(format!("yap-chunks-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&root).await;
+        let store = ObjectStore::new(&root);
+        let mut data: Vec<u8> = (0..12 * 1024 * 1024u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let path = root.join("data.bin");
+        tokio::fs::write(&path, &data).await.unwrap();
+        let first = store.save_chunks(&path, &ProgressBar::hidden()).await;
+
+        data.extend_from_slice(b"a few more rows");
+        tokio::fs::write(&path, &data).await.unwrap();
+        let second = store.save_chunks(&path, &ProgressBar::hidden()).await;
+        assert!(first.len() > 1);
+        assert_eq!(first[..first.len() - 1], second[..first.len() - 1]);
+
+        store.assemble("assembled", &second).await;
+        let assembled = tokio::fs::read(store.object_path("assembled"))
+            .await
+            .unwrap();
+        assert_eq!(assembled, data);
+    }
+}
@@ -0,0 +1,219 @@
+use super::{file::Logbook, ignores::IgnoreRules};
+use crate::config::Config;
+
+use clap::Args;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+const EXCLUDE_PREFIXES: [&str; 2] = [":!", ":^"];
+const GLOB_CHARS: [char; 4] = ['*', '?', '[', '{'];
+
+/// Files selected on the command line, resolved against the working tree and the tracked
+/// files so files missing locally can still be pushed or pulled
+#[derive(Debug, Args, Clone, Default)]
+pub struct PathSpec {
+    // Files, directories or globs like data/**/*.parquet. Prefix with :! to exclude
+    #[arg(short, long, num_args = 1..)]
+    paths: Vec<String>,
+
+    // Every tracked file, even the ones missing locally
+    #[arg(long, default_value_t = false)]
+    all: bool,
+}
+
+impl PathSpec {
+    /// Same spec with the paths relative to the repository root
+    fn relative_to(&self, config: &Config) -> Self {
+        let paths = self
+            .paths
+            .iter()
+            .map(|p| {
+                let prefix = EXCLUDE_PREFIXES.iter().find(|e| p.starts_with(*e));
+                let path = Path::new(&p[prefix.map_or(0, |e| e.len())..]);
+                format!(
+                    "{}{}",
+                    prefix.unwrap_or(&""),
+                    config.repository_path(path).display()
+                )
+            })
+            .collect();
+        Self {
+            paths,
+            all: self.all,
+        }
+    }
+
+    pub async fn resolve(&self, config: &Config) -> Vec<PathBuf> {
+        let tracked: BTreeSet<PathBuf> = Logbook::local(&config.local_db())
+            .await
+            .files_tracked()
+            .await
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        let mut ignores = IgnoreRules::new(config.root_dir(), config.ignore_patterns());
+        self.relative_to(config).resolve_in(&tracked, &mut ignores)
+    }
+
+    fn resolve_in(
+        &self,
+        tracked: &BTreeSet<PathBuf>,
+        ignores: &mut IgnoreRules,
+    ) -> Vec<PathBuf> {
+        let mut paths = BTreeSet::new();
+        if self.all {
+            paths.extend(tracked.iter().cloned());
+        }
+        for pattern in self.includes() {
+            if is_glob(pattern) {
+                let matcher = glob(pattern).compile_matcher();
+                paths.extend(
+                    ignores
+                        .walk(&glob_base(pattern))
+                        .into_iter()
+                        .filter(|p| matcher.is_match(p)),
+                );
+                paths.extend(tracked.iter().filter(|p| matcher.is_match(p)).cloned());
+                continue;
+            }
+            let path = normalize(Path::new(pattern));
+            let under: Vec<PathBuf> = tracked
+                .iter()
+                .filter(|p| is_under(p, &path))
+                .cloned()
+                .collect();
+            if path.is_dir() {
+                paths.extend(ignores.walk(&path));
+            } else if path.exists() || under.is_empty() {
+                // Unknown paths are kept so the command tells what is wrong with them
+                paths.insert(path);
+            }
+            paths.extend(under);
+        }
+        let excludes = matcher(self.excludes());
+        paths
+            .into_iter()
+            .filter(|p| !excludes.is_match(p))
+            .collect()
+    }
+
+    /// Keep the candidates selected by the spec. Without paths every candidate is.
+    pub fn select(&self, config: &Config, candidates: Vec<PathBuf>) -> Vec<PathBuf> {
+        self.relative_to(config).select_in(candidates)
+    }
+
+    fn select_in(&self, candidates: Vec<PathBuf>) -> Vec<PathBuf> {
+        let everything = self.all || self.includes().next().is_none();
+        let includes = matcher(self.includes());
+        let excludes = matcher(self.excludes());
+        candidates
+            .into_iter()
+            .filter(|p| (everything || includes.is_match(p)) && !excludes.is_match(p))
+            .collect()
+    }
+
+    fn includes(&self) -> impl Iterator<Item = &str> {
+        self.paths
+            .iter()
+            .map(String::as_str)
+            .filter(|p| !EXCLUDE_PREFIXES.iter().any(|e| p.starts_with(e)))
+    }
+
+    fn excludes(&self) -> impl Iterator<Item = &str> {
+        self.paths
+            .iter()
+            .filter_map(|p| EXCLUDE_PREFIXES.iter().find_map(|e| p.strip_prefix(e)))
+    }
+}
+
+/// Whether the path is the directory or inside it, `.` being the repository root
+pub fn is_under(path: &Path, dir: &Path) -> bool {
+    dir == Path::new(".") || path.starts_with(dir)
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(GLOB_CHARS)
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    path.strip_prefix("./").unwrap_or(path).to_path_buf()
+}
+
+fn glob(pattern: &str) -> Glob {
+    GlobBuilder::new(normalize(Path::new(pattern)).to_str().unwrap())
+        .literal_separator(true)
+        .build()
+        .unwrap_or_else(|err| panic!("invalid pattern {pattern}: {err}"))
+}
+
+/// Deepest directory without any glob character, where the walk starts
+fn glob_base(pattern: &str) -> PathBuf {
+    let base: PathBuf = normalize(Path::new(pattern))
+        .components()
+        .take_while(|c| !is_glob(&c.as_os_str().to_string_lossy()))
+        .collect();
+    match base.as_os_str().is_empty() {
+        true => PathBuf::from("."),
+        false => base,
+    }
+}
+
+/// Literal paths also match everything under them
+fn matcher<'a>(patterns: impl Iterator<Item = &'a str>) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(glob(pattern));
+        if !is_glob(pattern) {
+            builder.add(glob(&format!("{}/**", pattern.trim_end_matches('/'))));
+        }
+    }
+    builder.build().expect("unable to build the path patterns")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_pathspec() {
+        let dir =
+            std::env::temp_dir().join(format!("yap-pathspec-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("data/tmp")).unwrap();
+        for file in ["data/a.parquet", "data/tmp/b.parquet", "data/c.csv"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let mut ignores = IgnoreRules::new(&dir, &[]);
+        let tracked: BTreeSet<PathBuf> = ["data/remote.parquet", "data/c.csv"]
+            .into_iter()
+            .map(|p| dir.join(p))
+            .collect();
+        let spec = PathSpec {
+            paths: vec![
+                format!("{}/data/**/*.parquet", dir.display()),
+                format!(":!{}/data/tmp", dir.display()),
+            ],
+            all: false,
+        };
+        assert_eq!(
+            spec.resolve_in(&tracked, &mut ignores),
+            vec![dir.join("data/a.parquet"), dir.join("data/remote.parquet")]
+        );
+
+        let spec = PathSpec {
+            paths: vec![format!(":!{}/data/c.csv", dir.display())],
+            all: true,
+        };
+        assert_eq!(
+            spec.resolve_in(&tracked, &mut ignores),
+            vec![dir.join("data/remote.parquet")]
+        );
+        assert_eq!(
+            spec.select_in(tracked.into_iter().collect()),
+            vec![dir.join("data/remote.parquet")]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
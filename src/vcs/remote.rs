@@ -1,70 +1,580 @@
-use crate::vcs::{FileFacade, Remote};
+use crate::{
+    config::PushStrategy,
+    vcs::{FileFacade, Remote},
+};
 
-use indicatif::{ProgressBar, ProgressStyle};
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
+use super::{
+    file::File,
+    objects::{progress_style, Chunk},
+};
 
-pub async fn push_file(file: &FileFacade) -> Remote {
-    let remote = file.remote();
-    let start = std::time::Instant::now();
-    let _operator = remote.get_storage_operator();
-    let file_size = file.original_path().metadata().unwrap().len();
-    let progress = ProgressBar::new(file_size).with_message("Uploading file");
-    progress.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.cyan/blue} {bytes:>7}/{total_bytes:7} [{bytes_per_sec}] {msg}\n")
-            .unwrap()
-            .progress_chars("#->"),
-    );
-    let data = tokio::fs::read(file.original_path()).await.unwrap();
-    let _result = zstd::bulk::compress(&data, 0).unwrap();
+use indicatif::ProgressBar;
+use opendal::Operator;
+use std::{collections::HashSet, io::Write, path::Path};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const CHUNK_SIZE: usize = 5 * 1024 * 1024;
 
-    let mut file_content = File::open(file.original_path()).await.unwrap();
-    let mut buffer = vec![0; 5 * 1024 * 1024];
+/// Key of a compressed object on the remote. Objects are content addressed so the same key
+/// is shared by every file and branch having that content.
+pub fn object_key(object: &str) -> String {
+    format!("objects/{object}")
+}
+
+/// Key of the list of chunks of an object stored chunked
+pub fn manifest_key(object: &str) -> String {
+    format!("manifests/{object}")
+}
+
+/// Key holding the id of the latest object pushed for a file in a branch
+fn ref_key(file: &File) -> String {
+    format!("refs/{}/{}", file.branch(), file.path().to_str().unwrap())
+}
+
+fn progress_bar(len: u64, msg: &str) -> ProgressBar {
+    ProgressBar::new(len)
+        .with_message(msg.to_owned())
+        .with_style(progress_style())
+}
+
+/// Select the versions to send based on the strategy. Versions sharing the same object are
+/// only sent once.
+async fn versions_to_push(
+    operator: &Operator,
+    strategy: &PushStrategy,
+    versions: Vec<File>,
+) -> Vec<File> {
+    let candidates = match strategy {
+        PushStrategy::All => versions,
+        PushStrategy::Last => versions.into_iter().last().into_iter().collect(),
+        PushStrategy::Smart => {
+            let mut missing = Vec::new();
+            for version in versions {
+                let object = version.object();
+                let exists = |key: String| async move {
+                    operator.is_exist(&key).await.unwrap_or(false)
+                };
+                if !exists(object_key(&object)).await
+                    && !exists(manifest_key(&object)).await
+                {
+                    missing.push(version);
+                }
+            }
+            missing
+        },
+    };
+    let mut seen = HashSet::new();
+    candidates
+        .into_iter()
+        .filter(|v| seen.insert(v.object()))
+        .collect()
+}
+
+pub async fn push_object(
+    operator: &Operator,
+    path: &Path,
+    object: &str,
+    progress: &ProgressBar,
+) -> Result<(), String> {
+    let failed = |err: String| format!("unable to push {object}: {err}");
+    let mut file_content = fs::File::open(path)
+        .await
+        .map_err(|err| failed(format!("{:?}: {err}", path)))?;
+    let mut writer = operator
+        .writer(&object_key(object))
+        .await
+        .map_err(|err| failed(err.to_string()))?;
+    let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), 0).unwrap();
+    let mut buffer = vec![0; CHUNK_SIZE];
     loop {
-        let bytes_read = match file_content.read(&mut buffer).await {
-            Ok(n) => n,
-            Err(e) => {
-                eprintln!("Error reading from file: {}", e);
-                break;
-            },
-        };
+        let bytes_read = file_content
+            .read(&mut buffer)
+            .await
+            .map_err(|err| failed(format!("{:?}: {err}", path)))?;
         if bytes_read == 0 {
             break;
         }
+        encoder.write_all(&buffer[..bytes_read]).unwrap();
+        let compressed = std::mem::take(encoder.get_mut());
+        if !compressed.is_empty() {
+            writer
+                .write(compressed)
+                .await
+                .map_err(|err| failed(err.to_string()))?;
+        }
         progress.inc(bytes_read.try_into().unwrap());
-        zstd::stream::encode_all(&buffer[..bytes_read], 0).unwrap();
-        // operator
-        //     .write(file.path().to_str().unwrap(), result)
-        //     .await
-        //     .unwrap();
+    }
+    writer
+        .write(encoder.finish().unwrap())
+        .await
+        .map_err(|err| failed(err.to_string()))?;
+    writer.close().await.map_err(|err| failed(err.to_string()))
+}
+
+/// Download the object into the destination, decompressing it while it is read so big
+/// files never have to fit in memory
+pub async fn pull_object(
+    operator: &Operator,
+    object: &str,
+    destination: &Path,
+) -> Result<(), String> {
+    let failed = |err: String| format!("unable to pull {object}: {err}");
+    let mut reader = operator
+        .reader(&object_key(object))
+        .await
+        .map_err(|err| failed(err.to_string()))?;
+    fs::create_dir_all(destination.parent().expect("no parent for the object"))
+        .await
+        .map_err(|err| failed(err.to_string()))?;
+    // Write first to a temporary file so an interrupted pull never looks like a valid object
+    let tmp = destination.with_extension("tmp");
+    let mut output = fs::File::create(&tmp)
+        .await
+        .map_err(|err| failed(format!("{:?}: {err}", tmp)))?;
+    let mut decoder = zstd::stream::write::Decoder::new(Vec::new()).unwrap();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let bytes_read = reader
+            .read(&mut buffer)
+            .await
+            .map_err(|err| failed(err.to_string()))?;
+        if bytes_read == 0 {
+            break;
+        }
+        decoder
+            .write_all(&buffer[..bytes_read])
+            .map_err(|err| failed(err.to_string()))?;
+        let decompressed = std::mem::take(decoder.get_mut());
+        output
+            .write_all(&decompressed)
+            .await
+            .map_err(|err| failed(format!("{:?}: {err}", tmp)))?;
+    }
+    decoder.flush().map_err(|err| failed(err.to_string()))?;
+    output
+        .write_all(decoder.get_ref())
+        .await
+        .map_err(|err| failed(format!("{:?}: {err}", tmp)))?;
+    output
+        .flush()
+        .await
+        .map_err(|err| failed(format!("{:?}: {err}", tmp)))?;
+    fs::rename(&tmp, destination)
+        .await
+        .map_err(|err| failed(format!("{:?}: {err}", destination)))
+}
+
+/// Send the chunks of a version the remote doesn't have yet, then its manifest
+async fn push_chunks(
+    operator: &Operator,
+    version: &File,
+    chunks: &[Chunk],
+    progress: &ProgressBar,
+) -> Result<(), String> {
+    let store = version.store();
+    for chunk in chunks {
+        if operator
+            .is_exist(&object_key(&chunk.id))
+            .await
+            .unwrap_or(false)
+        {
+            progress.inc(chunk.size);
+            continue;
+        }
+        push_object(operator, &store.object_path(&chunk.id), &chunk.id, progress).await?;
+    }
+    operator
+        .write(
+            &manifest_key(&version.object()),
+            serde_json::to_vec(chunks).unwrap(),
+        )
+        .await
+        .map_err(|err| {
+            format!("unable to push the manifest of {}: {err}", version.object())
+        })
+}
+
+async fn pull_manifest(operator: &Operator, object: &str) -> Option<Vec<Chunk>> {
+    let manifest = operator.read(&manifest_key(object)).await.ok()?;
+    Some(serde_json::from_slice(&manifest).expect("invalid chunk manifest"))
+}
+
+/// Delete the objects and the manifests from the remote and return the bytes they used.
+/// Nothing is deleted on a dry run.
+pub async fn collect_garbage(
+    operator: &Operator,
+    objects: &HashSet<String>,
+    dry_run: bool,
+) -> u64 {
+    let mut reclaimed = 0;
+    for object in objects {
+        for key in [object_key(object), manifest_key(object)] {
+            let Ok(metadata) = operator.stat(&key).await else {
+                continue;
+            };
+            reclaimed += metadata.content_length();
+            if !dry_run {
+                operator
+                    .delete(&key)
+                    .await
+                    .unwrap_or_else(|err| panic!("unable to delete {key}: {err}"));
+            }
+        }
+    }
+    reclaimed
+}
+
+/// Make sure the object of the version is in the local history. Chunked versions are
+/// assembled from their chunks, fetching only the missing ones.
+pub async fn fetch_object(file: &FileFacade, version: &File) -> Result<(), String> {
+    let object = version.object();
+    let store = version.store();
+    if store.contains(&object) {
+        return Ok(());
+    }
+    let mut chunks = file.manifest(version).await;
+    if chunks.is_empty() || !store.has_chunks(&chunks) {
+        let operator = file.remote().get_storage_operator();
+        if chunks.is_empty() {
+            match pull_manifest(&operator, &object).await {
+                Some(manifest) => {
+                    file.save_manifest(&object, &manifest).await;
+                    chunks = manifest;
+                },
+                None => {
+                    return pull_object(&operator, &object, &store.object_path(&object))
+                        .await;
+                },
+            }
+        }
+        for chunk in chunks.iter().filter(|c| !store.contains(&c.id)) {
+            pull_object(&operator, &chunk.id, &store.object_path(&chunk.id)).await?;
+        }
+    }
+    store.assemble(&object, &chunks).await;
+    Ok(())
+}
+
+/// Push the versions of the file selected by the strategy and return the ones sent
+pub async fn push_file(file: &FileFacade) -> Result<(Remote, Vec<File>), String> {
+    let remote = file.remote();
+    let start = std::time::Instant::now();
+    let operator = remote.get_storage_operator();
+    let versions = file.versions().await;
+    let latest = versions.last().cloned();
+    let to_push = versions_to_push(&operator, &remote.strategy, versions).await;
+
+    let mut manifests = Vec::new();
+    for version in &to_push {
+        manifests.push(file.manifest(version).await);
+    }
+    let size = to_push
+        .iter()
+        .zip(&manifests)
+        .map(|(v, chunks)| match chunks.is_empty() {
+            true => v.history_path().metadata().unwrap().len(),
+            false => chunks.iter().map(|c| c.size).sum(),
+        })
+        .sum();
+    let progress = progress_bar(size, &format!("Uploading {:?}", file.path()));
+    for (version, chunks) in to_push.iter().zip(&manifests) {
+        match chunks.is_empty() {
+            true => {
+                push_object(
+                    &operator,
+                    &version.history_path(),
+                    &version.object(),
+                    &progress,
+                )
+                .await?
+            },
+            false => push_chunks(&operator, version, chunks, &progress).await?,
+        }
+    }
+    if let Some(latest) = latest {
+        operator
+            .write(&ref_key(&latest), latest.object())
+            .await
+            .map_err(|err| {
+                format!("unable to push the reference of {:?}: {err}", file.path())
+            })?;
     }
 
     progress
         .with_elapsed(start.elapsed())
         .finish_with_message("File uploaded");
 
-    Remote::new(file.path().to_path_buf(), remote.strategy, remote.storage)
+    Ok((
+        Remote::new(file.path().to_path_buf(), remote.strategy, remote.storage),
+        to_push,
+    ))
 }
 
-pub async fn pull_file(file: &FileFacade) -> Remote {
-    // TODO: when pushing and pulling from remote, how to know which files to get and send? keep
-    // the strategy think? probably no. How should we save the files on the remote? as they are in
-    // the history_path? probably.
-    if file.path().exists() {
-        println!("Already exists: {:?}", file.path())
-    }
+/// Fetch the object, or the latest version pushed for the file, store it in the history and
+/// restore the working file with it
+pub async fn pull_file(
+    file: &FileFacade,
+    object: Option<String>,
+) -> Result<(Remote, File), String> {
     let remote = file.remote();
-    let result = file
-        .remote()
-        .get_storage_operator()
-        .read(file.path().to_str().unwrap())
+    let operator = remote.get_storage_operator();
+    let object = match object {
+        Some(object) => object,
+        None => operator
+            .read(&ref_key(&file.file()))
+            .await
+            .map(|o| String::from_utf8(o).unwrap())
+            .map_err(|err| format!("{:?} has never been pushed: {err}", file.path()))?,
+    };
+    let version = file.file().set_object(&object);
+    fetch_object(file, &version).await?;
+    if file.original_path().exists() {
+        println!("Already exists, overriding: {:?}", file.path())
+    }
+    fs::create_dir_all(&file.original_path().parent().unwrap())
         .await
         .unwrap();
-    tokio::fs::create_dir_all(&file.path().parent().unwrap())
+    fs::copy(version.history_path(), file.original_path())
         .await
         .unwrap();
-    tokio::fs::write(&file.path(), &result).await.unwrap();
+    file.release_object(&version).await;
     println!("{:?} downloaded", file.path());
-    Remote::get(file.path().to_path_buf(), remote.storage)
+    Ok((
+        Remote::new(file.path().to_path_buf(), remote.strategy, remote.storage),
+        version,
+    ))
+}
+
+/// Forget the file on the remote. Objects may be shared with other files or branches so
+/// only the reference is removed.
+pub async fn remove_file(file: &FileFacade) -> Result<(), String> {
+    file.remote()
+        .get_storage_operator()
+        .delete(&ref_key(&file.file()))
+        .await
+        .map_err(|err| {
+            format!("unable to remove {:?} from the remote: {err}", file.path())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Author, RemoteConfig},
+        vcs::file::FileLogbook,
+    };
+
+    const CONTENT: &[u8] = b"name,value\nfirst,1\nsecond,2\n";
+
+    fn operator(config: &str) -> Operator {
+        toml::from_str::<RemoteConfig>(config)
+            .unwrap()
+            .get_storage_operator()
+    }
+
+    async fn working_file(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{name}", std::process::id()));
+        fs::write(&path, CONTENT).await.unwrap();
+        path
+    }
+
+    /// File with two versions in a repository of its own under the temp dir
+    async fn tracked_file(name: &str, remote: &str) -> FileFacade {
+        let dir = std::env::temp_dir().join(format!("yap-{name}-{}", std::process::id()));
+        let path = dir.join("data.csv");
+        fs::create_dir_all(&dir).await.unwrap();
+        let file = File::new(
+            &path,
+            "main",
+            dir.join("history").to_str().unwrap(),
+            0,
+            Author::default(),
+        );
+        let mut facade = FileFacade::new(file)
+            .set_logbook(FileLogbook::new(&path, &dir.join("logbooks")))
+            .set_remote(&toml::from_str::<RemoteConfig>(remote).unwrap())
+            .init()
+            .await;
+        for version in 1..=2 {
+            let content = format!("{name},{}\n", std::process::id()).repeat(version);
+            fs::write(&path, content).await.unwrap();
+            facade = facade.add().await;
+        }
+        facade
+    }
+
+    #[tokio::test]
+    async fn test_push_and_pull_file_with_each_strategy() {
+        // Whether each version ends up on the remote and how many a second push sends
+        for (strategy, sent, resent) in [
+            ("All", [true, true], 2),
+            ("Last", [false, true], 1),
+            ("Smart", [true, true], 0),
+        ] {
+            let name = format!("remote-{}", strategy.to_lowercase());
+            let remote = format!("storage = \"Memory\"\nstrategy = \"{strategy}\"");
+            let facade = tracked_file(&name, &remote).await.push().await.unwrap();
+            let operator = facade.remote().get_storage_operator();
+            let versions = facade.versions().await;
+            for (version, sent) in versions.iter().zip(sent) {
+                let key = object_key(&version.object());
+                assert_eq!(operator.is_exist(&key).await.unwrap(), sent, "{strategy}");
+            }
+            let (_, pushed) = push_file(&facade).await.unwrap();
+            assert_eq!(pushed.len(), resent, "{strategy}");
+
+            let latest = versions.last().unwrap();
+            let expected = fs::read(facade.original_path()).await.unwrap();
+            fs::remove_file(facade.original_path()).await.unwrap();
+            fs::remove_file(latest.history_path()).await.unwrap();
+            let (_, version) = pull_file(&facade, None).await.unwrap();
+            assert_eq!(version.object(), latest.object());
+            assert_eq!(fs::read(facade.original_path()).await.unwrap(), expected);
+            fs::remove_dir_all(facade.original_path().parent().unwrap())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_and_pull_object_in_memory() {
+        let path = working_file("yap-remote-memory.csv").await;
+        push_object(
+            &operator(r#"storage = "Memory""#),
+            &path,
+            "memoryobject",
+            &ProgressBar::hidden(),
+        )
+        .await
+        .unwrap();
+        // A new operator must see what the previous one pushed
+        let operator = operator(r#"storage = "Memory""#);
+        assert!(operator
+            .is_exist(&object_key("memoryobject"))
+            .await
+            .unwrap());
+        let pulled = path.with_extension("pulled");
+        pull_object(&operator, "memoryobject", &pulled)
+            .await
+            .unwrap();
+        assert_eq!(fs::read(&pulled).await.unwrap(), CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_push_and_pull_object_errors() {
+        let operator = operator(r#"storage = "Memory""#);
+        let path = working_file("yap-remote-errors.csv").await;
+        let missing = path.with_extension("missing");
+        let err = push_object(&operator, &missing, "errorobject", &ProgressBar::hidden())
+            .await
+            .unwrap_err();
+        assert!(err.contains("unable to push errorobject"), "{err}");
+
+        let err = pull_object(&operator, "errorobject", &missing)
+            .await
+            .unwrap_err();
+        assert!(err.contains("unable to pull errorobject"), "{err}");
+        // Nothing is left behind looking like a valid object
+        assert!(!missing.exists());
+    }
+
+    #[tokio::test]
+    async fn test_push_and_pull_object_in_fs() {
+        let path = working_file("yap-remote-fs.csv").await;
+        let root =
+            std::env::temp_dir().join(format!("yap-remote-fs-{}", std::process::id()));
+        let operator = operator(&format!(
+            r#"
+            storage = "Fs"
+            root = "{}"
+            "#,
+            root.to_str().unwrap()
+        ));
+        push_object(&operator, &path, "fsobject", &ProgressBar::hidden())
+            .await
+            .unwrap();
+        let stored = fs::read(root.join(object_key("fsobject"))).await.unwrap();
+        assert_ne!(stored, CONTENT);
+        let pulled = path.with_extension("pulled");
+        pull_object(&operator, "fsobject", &pulled).await.unwrap();
+        assert_eq!(fs::read(&pulled).await.unwrap(), CONTENT);
+
+        operator.delete(&object_key("fsobject")).await.unwrap();
+        assert!(!operator.is_exist(&object_key("fsobject")).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_push_pull_and_remove_file_in_memory_and_fs() {
+        let root =
+            std::env::temp_dir().join(format!("yap-remote-root-{}", std::process::id()));
+        for (name, remote) in [
+            ("remote-file-memory", r#"storage = "Memory""#.to_string()),
+            (
+                "remote-file-fs",
+                format!("storage = \"Fs\"\nroot = \"{}\"", root.display()),
+            ),
+        ] {
+            let facade = tracked_file(name, &remote).await;
+            let operator = facade.remote().get_storage_operator();
+            let versions = facade.versions().await;
+            let (first, latest) = (&versions[0], &versions[1]);
+            push_file(&facade).await.unwrap();
+            // The latest version pushed is found from the branch and the path
+            let key = format!("refs/main/{}", facade.path().to_str().unwrap());
+            assert_eq!(
+                operator.read(&key).await.unwrap(),
+                latest.object().as_bytes()
+            );
+
+            let (_, version) = pull_file(&facade, None).await.unwrap();
+            assert_eq!(version.object(), latest.object());
+            // Any version pushed can be pulled by its object
+            fs::remove_file(first.history_path()).await.unwrap();
+            let (_, version) = pull_file(&facade, Some(first.object())).await.unwrap();
+            assert_eq!(version.object(), first.object());
+            assert_eq!(
+                fs::read(facade.original_path()).await.unwrap(),
+                fs::read(first.history_path()).await.unwrap()
+            );
+
+            remove_file(&facade).await.unwrap();
+            assert!(!operator.is_exist(&key).await.unwrap());
+            // Other files or branches may share the objects
+            assert!(operator
+                .is_exist(&object_key(&latest.object()))
+                .await
+                .unwrap());
+            fs::remove_dir_all(facade.original_path().parent().unwrap())
+                .await
+                .unwrap();
+        }
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    // Needs a running MinIO, e.g.
+    // docker run -p 9000:9000 -e MINIO_ROOT_USER=yap -e MINIO_ROOT_PASSWORD=yapsecret minio/minio server /data
+    // with a bucket named yap-test and YAP_TEST_S3_SECRET=yapsecret
+    #[tokio::test]
+    #[ignore]
+    async fn test_push_and_pull_object_in_s3() {
+        let path = working_file("yap-remote-s3.csv").await;
+        let operator = operator(
+            r#"
+            storage = "S3"
+            bucket = "yap-test"
+            endpoint = "http://localhost:9000"
+            access_key = "yap"
+            credentials = "YAP_TEST_S3_SECRET"
+            "#,
+        );
+        push_object(&operator, &path, "s3object", &ProgressBar::hidden())
+            .await
+            .unwrap();
+        let pulled = path.with_extension("pulled");
+        pull_object(&operator, "s3object", &pulled).await.unwrap();
+        assert_eq!(fs::read(&pulled).await.unwrap(), CONTENT);
+        operator.delete(&object_key("s3object")).await.unwrap();
+    }
 }
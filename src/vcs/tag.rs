@@ -0,0 +1,294 @@
+use super::{
+    cli::current_branch,
+    file::{FileFacade, FileFacadeFactory, Logbook},
+    pathspec::is_under,
+};
+use crate::{config::Config, enums::OutputFormat};
+
+use clap::{Args, Subcommand};
+use serde::Serialize;
+use std::{collections::BTreeSet, fmt, path::PathBuf};
+
+/// Named snapshot of the versions of many files, kept in the root logbook
+#[derive(Debug, Clone, Serialize)]
+pub struct Tag {
+    pub name: String,
+    pub message: String,
+    pub branch: String,
+    pub author: String,
+    pub created_at: String,
+    pub files: u32,
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{}] {} files", self.name, self.branch, self.files)?;
+        if !self.created_at.is_empty() {
+            write!(f, " {}", self.created_at)?;
+        }
+        if !self.message.is_empty() {
+            write!(f, " - {}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Object the tag recorded for the file
+pub async fn tagged_object(
+    root_logbook: &Logbook,
+    tag: &str,
+    file: &FileFacade,
+) -> Result<String, String> {
+    if !root_logbook.tag_exists(tag).await {
+        return Err(format!("Tag {tag} doesn't exist"));
+    }
+    root_logbook
+        .tag_versions(tag)
+        .await
+        .into_iter()
+        .find(|(path, _)| path == file.path())
+        .map(|(_, object)| object)
+        .ok_or_else(|| format!("{:?} is not in the tag {tag}", file.path()))
+}
+
+#[derive(Debug, Args)]
+pub struct TagArgs {
+    #[command(subcommand)]
+    pub command: TagCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TagCommands {
+    /// Record the latest version of the selected tracked files under a name
+    #[command(arg_required_else_help = true)]
+    Create(CreateTag),
+
+    /// List the tags
+    List(ListTags),
+
+    /// Show the versions recorded by a tag
+    #[command(arg_required_else_help = true)]
+    Show(ShowTag),
+
+    /// Delete a tag. The versions it recorded are kept
+    #[command(arg_required_else_help = true)]
+    Delete(DeleteTag),
+}
+
+impl TagCommands {
+    pub async fn handle_commands(&self, config: &Config) -> i16 {
+        match self {
+            TagCommands::Create(args) => args.run(config).await,
+            TagCommands::List(args) => args.run(config).await,
+            TagCommands::Show(args) => args.run(config).await,
+            TagCommands::Delete(args) => args.run(config).await,
+        }
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct CreateTag {
+    #[arg(short, long)]
+    name: String,
+
+    #[arg(short, long, default_value = "")]
+    message: String,
+
+    // Files or directories to tag. Defaults to every tracked file
+    #[arg(short, long, num_args = 0..)]
+    paths: Vec<PathBuf>,
+
+    #[arg(short, long, required = false)]
+    branch: Option<String>,
+}
+
+impl CreateTag {
+    async fn run(&self, config: &Config) -> i16 {
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        if root_logbook.tag_exists(&self.name).await {
+            println!("Tag {} already exists", self.name);
+            return 1;
+        }
+        let branch = match &self.branch {
+            Some(branch) => branch.to_owned(),
+            None => current_branch(config).await,
+        };
+        let selected = config.repository_paths(&self.paths);
+        let tracked: BTreeSet<PathBuf> = root_logbook
+            .files_tracked_in(&branch)
+            .await
+            .into_iter()
+            .map(PathBuf::from)
+            .filter(|p| selected.is_empty() || selected.iter().any(|s| is_under(p, s)))
+            .collect();
+        let files =
+            FileFacadeFactory::new(tracked.into_iter().collect(), &branch, config);
+        let mut versions = Vec::new();
+        for file in files {
+            if !file.has_logbook() {
+                continue;
+            }
+            let file = file.init().await;
+            if let Some(latest) = file.previous_version().await {
+                versions.push((file.path().to_path_buf(), latest.file().object()));
+            }
+        }
+        if versions.is_empty() {
+            println!("No version to tag in branch {branch}");
+            return 1;
+        }
+        let tag = Tag {
+            name: self.name.clone(),
+            message: self.message.clone(),
+            branch,
+            author: config.author().pk(),
+            created_at: String::new(),
+            files: versions.len() as u32,
+        };
+        root_logbook.create_tag(&tag, &versions).await;
+        println!("Tag {} created with {} files", tag.name, tag.files);
+        0
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct ListTags {
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+}
+
+impl ListTags {
+    async fn run(&self, config: &Config) -> i16 {
+        let tags = Logbook::local(&config.local_db()).await.tags().await;
+        match self.format {
+            OutputFormat::Human => tags.iter().for_each(|t| println!("{t}")),
+            OutputFormat::Json => {
+                println!("{}", serde_json::to_string_pretty(&tags).unwrap())
+            },
+        }
+        0
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct ShowTag {
+    #[arg(short, long)]
+    name: String,
+
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+}
+
+impl ShowTag {
+    async fn run(&self, config: &Config) -> i16 {
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        if !root_logbook.tag_exists(&self.name).await {
+            println!("Tag {} doesn't exist", self.name);
+            return 1;
+        }
+        let versions = root_logbook.tag_versions(&self.name).await;
+        match self.format {
+            OutputFormat::Human => versions
+                .iter()
+                .for_each(|(path, object)| println!("{object} {}", path.display())),
+            OutputFormat::Json => {
+                let versions: Vec<serde_json::Value> = versions
+                    .iter()
+                    .map(|(path, object)| serde_json::json!({"path": path, "object": object}))
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&versions).unwrap())
+            },
+        }
+        0
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct DeleteTag {
+    #[arg(short, long)]
+    name: String,
+}
+
+impl DeleteTag {
+    async fn run(&self, config: &Config) -> i16 {
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        if !root_logbook.tag_exists(&self.name).await {
+            println!("Tag {} doesn't exist", self.name);
+            return 1;
+        }
+        root_logbook.delete_tag(&self.name).await;
+        println!("Tag {} deleted", self.name);
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcs::testing::Repository;
+
+    async fn commit(repository: &Repository, path: &str, content: &str) {
+        repository.write(path, content).await;
+        assert_eq!(
+            repository.run(&["commit", "-p", path, "-m", "edit"]).await,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tag_snapshot_and_checkout() {
+        let repository = Repository::new("tag").await;
+        repository.write("a.csv", "a\n1\n").await;
+        repository.write("b.csv", "b\n1\n").await;
+        assert_eq!(repository.run(&["add", "-p", "a.csv", "b.csv"]).await, 0);
+        commit(&repository, "a.csv", "a\n2\n").await;
+
+        assert_eq!(repository.run(&["tag", "create", "-n", "v1"]).await, 0);
+        assert_eq!(repository.run(&["tag", "create", "-n", "v1"]).await, 1);
+        let config = repository.config();
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        let mut latest = Vec::new();
+        for file in FileFacadeFactory::new(
+            vec![PathBuf::from("a.csv"), PathBuf::from("b.csv")],
+            "main",
+            &config,
+        ) {
+            let file = file.init().await;
+            let version = file.previous_version().await.unwrap();
+            latest.push((file.path().to_path_buf(), version.file().object()));
+        }
+        assert_eq!(root_logbook.tag_versions("v1").await, latest);
+
+        commit(&repository, "a.csv", "a\n3\n").await;
+        commit(&repository, "b.csv", "b\n2\n").await;
+        assert_eq!(repository.run(&["checkout", "--tag", "v1"]).await, 0);
+        assert_eq!(repository.read("a.csv").await, "a\n2\n");
+        assert_eq!(repository.read("b.csv").await, "b\n1\n");
+    }
+
+    #[tokio::test]
+    async fn test_tagged_object_errors() {
+        let repository = Repository::new("tag-errors").await;
+        repository.write("a.csv", "a\n1\n").await;
+        repository.write("b.csv", "b\n1\n").await;
+        assert_eq!(repository.run(&["add", "-p", "a.csv"]).await, 0);
+        assert_eq!(repository.run(&["tag", "create", "-n", "v1"]).await, 0);
+        assert_eq!(repository.run(&["add", "-p", "b.csv"]).await, 0);
+
+        let config = repository.config();
+        let root_logbook = Logbook::local(&config.local_db()).await;
+        let mut files = FileFacadeFactory::new(
+            vec![PathBuf::from("a.csv"), PathBuf::from("b.csv")],
+            "main",
+            &config,
+        );
+        let (a, b) = (files.next().unwrap(), files.next().unwrap());
+        assert!(tagged_object(&root_logbook, "v1", &a).await.is_ok());
+        let err = tagged_object(&root_logbook, "v1", &b).await.unwrap_err();
+        assert!(err.contains("is not in the tag v1"), "{err}");
+        let err = tagged_object(&root_logbook, "missing", &a)
+            .await
+            .unwrap_err();
+        assert_eq!(err, "Tag missing doesn't exist");
+    }
+}
@@ -0,0 +1,71 @@
+use super::cli::VcsCommands;
+use crate::config::Config;
+
+use clap::Parser;
+use std::{env, path::PathBuf};
+use tokio::sync::{Mutex, MutexGuard};
+
+/// The commands work from the current directory, so the tests moving into a repository run
+/// one at a time
+static CURRENT_DIR: Mutex<()> = Mutex::const_new(());
+
+#[derive(Debug, Parser)]
+struct Yap {
+    #[command(subcommand)]
+    command: VcsCommands,
+}
+
+/// Repository of its own under the temp dir, on the main branch, for the tests running the
+/// vcs commands
+pub struct Repository {
+    pub dir: PathBuf,
+    _current_dir: MutexGuard<'static, ()>,
+}
+
+impl Repository {
+    pub async fn new(name: &str) -> Self {
+        let current_dir = CURRENT_DIR.lock().await;
+        let dir = env::temp_dir().join(format!("yap-{name}-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        env::set_current_dir(&dir).unwrap();
+        Config::init().await;
+        Config::new().unwrap().set_branch("main").save();
+        Self {
+            dir,
+            _current_dir: current_dir,
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        Config::new().unwrap()
+    }
+
+    /// Run the command like `yap vcs` would and return its exit code
+    pub async fn run(&self, args: &[&str]) -> i16 {
+        Yap::try_parse_from(std::iter::once("vcs").chain(args.iter().copied()))
+            .unwrap_or_else(|err| panic!("{err}"))
+            .command
+            .handle_commands()
+            .await
+    }
+
+    pub async fn write(&self, path: &str, content: &str) {
+        tokio::fs::write(self.dir.join(path), content)
+            .await
+            .unwrap();
+    }
+
+    pub async fn read(&self, path: &str) -> String {
+        tokio::fs::read_to_string(self.dir.join(path))
+            .await
+            .unwrap_or_else(|err| panic!("{path}: {err}"))
+    }
+}
+
+impl Drop for Repository {
+    fn drop(&mut self) {
+        let _ = env::set_current_dir(env::temp_dir());
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -4,7 +4,10 @@ use super::{
     comparaison::Diff,
     file::{File, LogbookProvider},
 };
+use chrono::{Local, TimeZone};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{path::PathBuf, str::FromStr};
 use tokio::process::Command;
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Default, Serialize)]
@@ -17,6 +20,8 @@ pub struct Commit {
     file_to: File,
     git_commit: String,
     message: String,
+    // Repository commit grouping the versions commited together
+    root_commit: u32,
 }
 
 impl Commit {
@@ -36,9 +41,19 @@ impl Commit {
             file_from,
             message,
             git_commit: String::new(),
+            root_commit: 0,
         }
     }
 
+    pub fn set_root_commit(mut self, root_commit: u32) -> Self {
+        self.root_commit = root_commit;
+        self
+    }
+
+    pub fn diff(&self) -> Option<&Diff> {
+        self.diff.as_ref()
+    }
+
     pub fn set_git_commit(mut self, commit: String) -> Self {
         self.git_commit = commit;
         self
@@ -59,29 +74,239 @@ impl Commit {
 
 impl LogbookProvider for Commit {
     async fn query(&self) -> String {
-        "INSERT INTO commits (git_commit, message, file_from, file_to, diff, branch, author) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)".to_string()
+        "INSERT INTO commits (git_commit, message, file_from, file_to, diff, branch, author, timestamp, root_commit) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)".to_string()
     }
     async fn params(&self) -> Vec<String> {
         //TODO: fix the type return. return the thing of params from libsql
         vec![
             self.git_commit.clone(),
             self.message.clone(),
-            self.file_from.history_path().to_str().unwrap().to_string(),
-            self.file_to.history_path().to_str().unwrap().to_string(),
+            self.file_from.object(),
+            self.file_to.object(),
             self.diff_pk(),
             self.branch.clone(),
             self.author.pk(), //TODO: fix all the structs that should be the ids
+            self.file_to.timestamp().to_string(),
+            self.root_commit.to_string(),
         ]
     }
 }
 
+/// Filters applied when reading the commits back from the logbooks
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    pub branch: Option<String>,
+    pub author: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+/// A commit as read back from a file logbook
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub id: u32,
+    // 0 for the commits made before they were grouped, or by a merge
+    pub root_commit: u32,
+    pub path: PathBuf,
+    pub timestamp: i64,
+    pub author: String,
+    pub branch: String,
+    pub git_commit: String,
+    pub message: String,
+    pub technique: Option<String>,
+    pub diff: Option<Value>,
+}
+
+/// Versions of many files commited together in one invocation
+#[derive(Debug, Clone, Serialize)]
+pub struct CommitGroup {
+    pub id: Option<u32>,
+    pub timestamp: i64,
+    pub author: String,
+    pub branch: String,
+    pub git_commit: String,
+    pub message: String,
+    pub files: Vec<LogEntry>,
+}
+
+impl CommitGroup {
+    /// Group the entries by repository commit, keeping the order of their first entry
+    pub fn group(entries: Vec<LogEntry>) -> Vec<CommitGroup> {
+        let mut groups: Vec<CommitGroup> = Vec::new();
+        for entry in entries {
+            let id = (entry.root_commit != 0).then_some(entry.root_commit);
+            match groups.iter_mut().find(|g| id.is_some() && g.id == id) {
+                Some(group) => group.files.push(entry),
+                None => groups.push(CommitGroup {
+                    id,
+                    timestamp: entry.timestamp,
+                    author: entry.author.clone(),
+                    branch: entry.branch.clone(),
+                    git_commit: entry.git_commit.clone(),
+                    message: entry.message.clone(),
+                    files: vec![entry],
+                }),
+            }
+        }
+        groups
+    }
+}
+
+impl std::fmt::Display for CommitGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let date = Local
+            .timestamp_opt(self.timestamp, 0)
+            .single()
+            .map(|d| d.to_rfc2822())
+            .unwrap_or_default();
+        match self.id {
+            Some(id) => writeln!(f, "commit {id}")?,
+            None => writeln!(f, "commit {} {:?}", self.files[0].id, self.files[0].path)?,
+        }
+        writeln!(f, "Branch: {}", self.branch)?;
+        writeln!(f, "Author: {}", self.author)?;
+        writeln!(f, "Date:   {}", date)?;
+        if !self.git_commit.is_empty() {
+            writeln!(f, "Git:    {}", self.git_commit)?;
+        }
+        writeln!(f, "\n    {}\n", self.message)?;
+        for entry in &self.files {
+            match (&entry.technique, &entry.diff) {
+                (Some(technique), Some(diff)) => {
+                    writeln!(f, "    {:?} ({}): {}", entry.path, technique, diff)?
+                },
+                _ => writeln!(f, "    {:?}", entry.path)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum FileStatus {
+    Modified,
+    Unchanged,
+    Missing,
+    Untracked,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusEntry {
+    pub path: PathBuf,
+    pub status: FileStatus,
+    // Versions saved locally that were never pushed to any remote
+    pub unpushed: u32,
+}
+
+impl std::fmt::Display for StatusEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:>10}: {}",
+            format!("{:?}", self.status),
+            self.path.display()
+        )?;
+        if self.unpushed > 0 {
+            write!(f, " ({} unpushed)", self.unpushed)?;
+        }
+        Ok(())
+    }
+}
+
+/// The different ways to point at a version of a file. As a string it can be `working`,
+/// `latest`, a commit id, `@<timestamp>` or a git commit hash.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VersionRef {
+    Working,
+    Latest,
+    Commit(u32),
+    Timestamp(i64),
+    GitCommit(String),
+    // Object recorded by a tag, never parsed from the command line
+    Object(String),
+}
+
+impl FromStr for VersionRef {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "working" => Ok(Self::Working),
+            "latest" => Ok(Self::Latest),
+            v if v.starts_with('@') => v[1..]
+                .parse::<i64>()
+                .map(Self::Timestamp)
+                .map_err(|err| format!("invalid timestamp {v}: {err}")),
+            v if v.chars().all(|c| c.is_ascii_digit()) => v
+                .parse::<u32>()
+                .map(Self::Commit)
+                .map_err(|err| format!("invalid commit id {v}: {err}")),
+            v if v.len() >= 4 && v.chars().all(|c| c.is_ascii_hexdigit()) => {
+                Ok(Self::GitCommit(v.to_owned()))
+            },
+            v => Err(format!(
+                "{v} is not a version, use working, latest, a commit id, @<timestamp> or a git commit"
+            )),
+        }
+    }
+}
+
 pub async fn get_latest_git_commit() -> String {
     match Command::new("git")
         .args(["rev-parse", "HEAD"])
         .output()
         .await
     {
-        Ok(v) => String::from_utf8(v.stdout).unwrap(),
+        Ok(v) => String::from_utf8(v.stdout).unwrap().trim().to_owned(),
         Err(_err) => String::new(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version_ref() {
+        assert_eq!("working".parse::<VersionRef>(), Ok(VersionRef::Working));
+        assert_eq!("latest".parse::<VersionRef>(), Ok(VersionRef::Latest));
+        assert_eq!("12".parse::<VersionRef>(), Ok(VersionRef::Commit(12)));
+        assert_eq!(
+            "@1711000000".parse::<VersionRef>(),
+            Ok(VersionRef::Timestamp(1711000000))
+        );
+        assert_eq!(
+            "a1b2c3d".parse::<VersionRef>(),
+            Ok(VersionRef::GitCommit("a1b2c3d".to_string()))
+        );
+        assert!("@yesterday".parse::<VersionRef>().is_err());
+        assert!("main".parse::<VersionRef>().is_err());
+    }
+
+    #[test]
+    fn test_group_commits() {
+        let entry = |id: u32, root_commit: u32, path: &str| LogEntry {
+            id,
+            root_commit,
+            path: PathBuf::from(path),
+            timestamp: 1711000000,
+            author: String::new(),
+            branch: "main".to_string(),
+            git_commit: String::new(),
+            message: "msg".to_string(),
+            technique: None,
+            diff: None,
+        };
+        let groups = CommitGroup::group(vec![
+            entry(3, 2, "a.csv"),
+            entry(1, 0, "c.csv"),
+            entry(2, 0, "d.csv"),
+            entry(5, 2, "b.csv"),
+        ]);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].id, Some(2));
+        assert_eq!(groups[0].files.len(), 2);
+        assert_eq!(groups[1].id, None);
+        assert_eq!(groups[2].files[0].path, PathBuf::from("d.csv"));
+    }
+}